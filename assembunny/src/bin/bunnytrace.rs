@@ -0,0 +1,106 @@
+use assembunny::{Register, TraceReader, TraceStep};
+use clap::{Parser, Subcommand};
+use itertools::{EitherOrBoth, Itertools};
+use std::{fs::File, io::BufReader};
+
+/// Reads the traces written by the runners' --trace option.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print every step of a trace with the registers after it
+    Replay { file: String },
+    /// Find the first step where two traces differ
+    Diff { left: String, right: String },
+}
+
+fn open(path: &str) -> TraceReader<BufReader<File>> {
+    File::open(path)
+        .and_then(|file| TraceReader::new(BufReader::new(file)))
+        .unwrap_or_else(|error| {
+            eprintln!("{}: {}", path, error);
+            std::process::exit(1);
+        })
+}
+
+fn read(path: &str, step: std::io::Result<TraceStep>) -> TraceStep {
+    step.unwrap_or_else(|error| {
+        eprintln!("{}: {}", path, error);
+        std::process::exit(1);
+    })
+}
+
+fn registers(registers: &[i64; 4]) -> String {
+    Register::ALL
+        .iter()
+        .map(|reg| format!("{}={}", reg, registers[*reg as usize]))
+        .join(" ")
+}
+
+fn describe(step: &TraceStep) -> String {
+    format!(
+        "{:>4}: {:<14} {}",
+        step.pc,
+        step.instruction.to_string(),
+        registers(&step.registers)
+    )
+}
+
+fn main() {
+    let args = Args::parse();
+
+    match args.command {
+        Command::Replay { file } => {
+            let trace = open(&file);
+            println!("{:>8}  start {}", 0, registers(&trace.initial_registers()));
+            for step in trace {
+                let step = read(&file, step);
+                println!("{:>8}  {}", step.step, describe(&step));
+            }
+        }
+        Command::Diff { left, right } => {
+            let left_trace = open(&left);
+            let right_trace = open(&right);
+            if left_trace.initial_registers() != right_trace.initial_registers() {
+                println!("The traces start differently");
+                println!("  {}: {}", left, registers(&left_trace.initial_registers()));
+                println!(
+                    "  {}: {}",
+                    right,
+                    registers(&right_trace.initial_registers())
+                );
+                std::process::exit(1);
+            }
+
+            let mut steps = 0;
+            for pair in left_trace.zip_longest(right_trace) {
+                let (difference, left_step, right_step) = match pair {
+                    EitherOrBoth::Both(l, r) => {
+                        let (l, r) = (read(&left, l), read(&right, r));
+                        if l == r {
+                            steps += 1;
+                            continue;
+                        }
+                        ("differ", describe(&l), describe(&r))
+                    }
+                    EitherOrBoth::Left(l) => {
+                        ("end", describe(&read(&left, l)), "(ended)".to_string())
+                    }
+                    EitherOrBoth::Right(r) => {
+                        ("end", "(ended)".to_string(), describe(&read(&right, r)))
+                    }
+                };
+                println!("The traces {} at step {}", difference, steps + 1);
+                println!("  {}: {}", left, left_step);
+                println!("  {}: {}", right, right_step);
+                std::process::exit(1);
+            }
+            println!("The traces are the same for all {} steps", steps);
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use crate::{
+    debugger::Debugger,
+    machine::{Machine, OutputSink, Status, Stdout, Width},
+    parser::parse_register,
+    profiler::Profile,
+    trace::TraceWriter,
+    Register,
+};
+use clap::ValueEnum;
+use itertools::Itertools;
+use std::{
+    fs::File,
+    io::BufWriter,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Registers: a b c d
+    Text,
+    /// The registers, instruction count and halting pc as JSON
+    Json,
+}
+
+/// How the program is prepared, flattened into each day's `Args`.
+#[derive(clap::Args, Debug)]
+pub struct ProgramArgs {
+    /// Rewrite addition and multiplication loops into single instructions
+    #[arg(long)]
+    pub optimize: bool,
+    /// Print the program as pseudo-code instead of running it
+    #[arg(long)]
+    pub decompile: bool,
+}
+
+impl ProgramArgs {
+    /// Optimizes the machine's program if `--optimize` was given.
+    pub fn prepare(&self, machine: &mut Machine) {
+        if self.optimize {
+            machine.optimize();
+        }
+    }
+}
+
+/// Options for running a program once, flattened into each day's `Args`.
+#[derive(clap::Args, Debug)]
+pub struct RunArgs {
+    /// Set a register before running, e.g. --reg a=7 --reg c=1
+    #[arg(long = "reg", value_name = "REG=VALUE", value_parser = parse_assignment)]
+    pub registers: Vec<(Register, i64)>,
+    /// How wide the registers are, arithmetic that doesn't fit is an error
+    #[arg(long, value_enum, default_value_t = Width::I32)]
+    pub width: Width,
+    #[command(flatten)]
+    pub limits: LimitArgs,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+    /// Print how often each instruction ran to stderr
+    #[arg(long)]
+    pub profile: bool,
+    /// Record every step to a binary trace file, see the trace tool
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<String>,
+    /// Step through the program in an interactive debugger, which has no
+    /// limits, trace, profile or output format of its own
+    #[arg(long, conflicts_with_all = ["max_steps", "timeout", "profile", "trace", "output"])]
+    pub interactive: bool,
+}
+
+impl RunArgs {
+    /// Sets the width and the registers given with `--reg`, then checks that
+    /// every register fits the width, so a run can't start out of range.
+    pub fn apply(&self, machine: &mut Machine) -> Result<(), String> {
+        machine.set_width(self.width);
+        for (reg, value) in self.registers.iter() {
+            machine.set_register(*reg, *value);
+        }
+        for reg in Register::ALL {
+            check_width(self.width, reg, machine.register(reg))?;
+        }
+        Ok(())
+    }
+
+    /// Prints the final state in the chosen format, and the profile if it
+    /// was asked for.
+    pub fn report(&self, machine: &Machine, profile: &Profile) {
+        if self.profile {
+            eprint!("{}", profile.report(machine.instructions()));
+        }
+        match self.output {
+            OutputFormat::Text => {
+                print!("Registers: ");
+                machine
+                    .registers()
+                    .iter()
+                    .for_each(|reg| print!("{} ", reg));
+                println!();
+            }
+            OutputFormat::Json => println!(
+                "{}",
+                to_json(machine.registers(), Some(machine.steps()), machine.pc())
+            ),
+        }
+    }
+
+    /// Prints the final state of a native run, which doesn't count the
+    /// instructions it executes.
+    pub fn report_native(&self, registers: &[i64; 4], pc: i64) {
+        match self.output {
+            OutputFormat::Text => {
+                print!("Registers: ");
+                registers.iter().for_each(|reg| print!("{} ", reg));
+                println!();
+            }
+            OutputFormat::Json => println!("{}", to_json(registers, None, pc)),
+        }
+    }
+
+    /// Runs the machine as the options say: in the debugger with
+    /// `--interactive`, otherwise to the end, then reports. With `debug`
+    /// each instruction is printed before it runs and the registers after.
+    pub fn execute(&self, machine: &mut Machine, debug: bool) -> Result<(), String> {
+        if self.interactive {
+            let stdin = std::io::stdin();
+            return Debugger::new(machine, &mut Stdout)
+                .repl(stdin.lock(), std::io::stdout())
+                .map_err(|error| format!("Failed to run debugger: {}", error));
+        }
+        let profile = self.run(machine, &mut Stdout, debug)?;
+        self.report(machine, &profile);
+        Ok(())
+    }
+
+    /// Runs the machine until it halts or the sink stops it, profiling it,
+    /// recording the trace and checking the limits. The trace is finished
+    /// however the run ends, so one that fails can still be replayed.
+    pub fn run(
+        &self,
+        machine: &mut Machine,
+        sink: &mut dyn OutputSink,
+        debug: bool,
+    ) -> Result<Profile, String> {
+        let mut profile = Profile::new(machine);
+        let limits = self.limits();
+        let mut trace = self.trace_writer(machine)?;
+
+        let result = loop {
+            let pc = machine.pc();
+            // Fetched first, as an optimized loop may be put back before it runs
+            let instruction = machine.fetch();
+            if debug {
+                if let Some(instruction) = instruction {
+                    println!("{}: {}", pc, instruction);
+                }
+            }
+            let status = profile.step(machine, sink);
+            if let (Some(trace), Some(instruction)) = (trace.as_mut(), instruction) {
+                if status != Status::Overflowed {
+                    if let Err(error) = trace.record(pc, &instruction, machine.registers()) {
+                        break Err(format!("Failed to write trace: {}", error));
+                    }
+                }
+            }
+            if debug {
+                machine
+                    .registers()
+                    .iter()
+                    .for_each(|reg| print!("{} ", reg));
+                println!();
+            }
+            if let Err(error) = limits.check(machine, status) {
+                break Err(error);
+            }
+            if status != Status::Running {
+                break Ok(());
+            }
+        };
+
+        let finished = trace.map(|trace| trace.finish());
+        result?;
+        if let Some(Err(error)) = finished {
+            return Err(format!("Failed to write trace: {}", error));
+        }
+        Ok(profile)
+    }
+
+    /// Starts the `--trace` file, if there is one, from the machine's
+    /// current registers.
+    pub fn trace_writer(
+        &self,
+        machine: &Machine,
+    ) -> Result<Option<TraceWriter<BufWriter<File>>>, String> {
+        let Some(path) = &self.trace else {
+            return Ok(None);
+        };
+        File::create(path)
+            .and_then(|file| TraceWriter::new(BufWriter::new(file), machine.registers()))
+            .map(Some)
+            .map_err(|error| format!("Failed to write trace {}: {}", path, error))
+    }
+
+    /// Starts the clock for `--timeout`.
+    pub fn limits(&self) -> Limits {
+        self.limits.start()
+    }
+}
+
+/// How long a run may go on for, flattened into `RunArgs` and into runners
+/// that run the program more than once.
+#[derive(clap::Args, Debug)]
+pub struct LimitArgs {
+    /// Give up after running this many instructions
+    #[arg(long)]
+    pub max_steps: Option<u64>,
+    /// Give up after this many seconds
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    pub timeout: Option<Duration>,
+}
+
+impl LimitArgs {
+    /// Starts the clock for a run.
+    pub fn start(&self) -> Limits {
+        Limits {
+            max_steps: self.max_steps,
+            timeout: self
+                .timeout
+                .map(|timeout| (timeout, Instant::now() + timeout)),
+        }
+    }
+}
+
+/// The `--max-steps` and `--timeout` limits for one run.
+pub struct Limits {
+    max_steps: Option<u64>,
+    timeout: Option<(Duration, Instant)>,
+}
+
+impl Limits {
+    /// Checks the machine after a step. Running out of steps or time and
+    /// overflowing a register are errors, described along with the state
+    /// the machine was left in.
+    pub fn check(&self, machine: &Machine, status: Status) -> Result<(), String> {
+        let problem = if status == Status::Overflowed {
+            let instruction = machine.current().map(|i| i.to_string()).unwrap_or_default();
+            format!(
+                "Overflow at pc {} ({}) with {} registers",
+                machine.pc(),
+                instruction,
+                machine.width()
+            )
+        } else if status != Status::Running {
+            return Ok(());
+        } else if self.max_steps.is_some_and(|max| machine.steps() >= max) {
+            format!("Stopped after {} steps", machine.steps())
+        } else if let Some((timeout, _)) = self.timeout.filter(|(_, deadline)| {
+            // Only look at the clock now and then, it's slow next to a step
+            machine.steps().is_multiple_of(4096) && Instant::now() >= *deadline
+        }) {
+            format!("Timed out after {:?}", timeout)
+        } else {
+            return Ok(());
+        };
+
+        Err(format!("{}\n{}", problem, describe(machine)))
+    }
+}
+
+/// The machine state for error messages.
+pub fn describe(machine: &Machine) -> String {
+    format!(
+        "pc={} steps={} {}",
+        machine.pc(),
+        machine.steps(),
+        Register::ALL
+            .iter()
+            .map(|reg| format!("{}={}", reg, machine.register(*reg)))
+            .join(" ")
+    )
+}
+
+/// Checks that a register's starting value fits the width.
+pub fn check_width(width: Width, reg: Register, value: i64) -> Result<(), String> {
+    if width.contains(value) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Register {} can't start at {}, it doesn't fit in {} registers",
+            reg, value, width
+        ))
+    }
+}
+
+/// Parses a duration in seconds, fractions allowed.
+pub fn parse_seconds(text: &str) -> Result<Duration, String> {
+    text.parse::<f64>()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("Expected a number of seconds, got \"{}\"", text))
+}
+
+/// Parses a register name, `a` to `d`.
+pub fn parse_register_name(text: &str) -> Result<Register, String> {
+    parse_register(text.trim()).ok_or_else(|| format!("Expected a register a-d, got \"{}\"", text))
+}
+
+/// Parses a `REG=VALUE` register assignment such as `a=7`.
+pub fn parse_assignment(text: &str) -> Result<(Register, i64), String> {
+    let (reg, value) = text
+        .split_once('=')
+        .ok_or_else(|| format!("Expected REG=VALUE, got \"{}\"", text))?;
+    let reg = parse_register_name(reg)?;
+    let value = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid value \"{}\"", value))?;
+    Ok((reg, value))
+}
+
+fn to_json(registers: &[i64; 4], steps: Option<u64>, pc: i64) -> String {
+    format!(
+        "{{\"registers\":{{{}}},\"instructions\":{},\"pc\":{}}}",
+        Register::ALL
+            .iter()
+            .map(|reg| format!("\"{}\":{}", reg, registers[*reg as usize]))
+            .join(","),
+        steps.map_or("null".to_string(), |steps| steps.to_string()),
+        pc
+    )
+}
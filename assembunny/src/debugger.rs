@@ -0,0 +1,350 @@
+use crate::{
+    instruction::{Instruction, Register},
+    machine::{Machine, OutputSink, Status},
+    parser::parse_register,
+};
+use itertools::Itertools;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+Commands:
+  step [n]                 Run n instructions (default 1)
+  continue                 Run until a breakpoint or the program halts
+  break <pc>               Stop before the instruction at pc
+  break <reg> <op> <value> Stop when a register matches, op is one of == != < <= > >=
+  delete <n>               Remove breakpoint n
+  breakpoints              List the breakpoints
+  regs                     Show the registers
+  set <reg> <value>        Change a register
+  disasm [n]               Show n instructions either side of pc (default 5)
+  changes                  List instructions changed since the debugger started
+  help                     Show this message
+  quit                     Stop debugging";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn parse(text: &str) -> Option<Comparison> {
+        match text {
+            "==" => Some(Comparison::Equal),
+            "!=" => Some(Comparison::NotEqual),
+            "<" => Some(Comparison::Less),
+            "<=" => Some(Comparison::LessOrEqual),
+            ">" => Some(Comparison::Greater),
+            ">=" => Some(Comparison::GreaterOrEqual),
+            _ => None,
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        }
+    }
+
+    fn matches(&self, left: i64, right: i64) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Breakpoint {
+    Pc(i64),
+    Register(Register, Comparison, i64),
+}
+
+impl Breakpoint {
+    fn hit(&self, machine: &Machine) -> bool {
+        match self {
+            Breakpoint::Pc(pc) => machine.pc() == *pc,
+            Breakpoint::Register(reg, comparison, value) => {
+                comparison.matches(machine.register(*reg), *value)
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Breakpoint::Pc(pc) => format!("pc {}", pc),
+            Breakpoint::Register(reg, comparison, value) => {
+                format!("{} {} {}", reg, comparison.symbol(), value)
+            }
+        }
+    }
+}
+
+/// An interactive debugger reading commands from `input` and writing to
+/// `output`. Instructions changed while debugging, by `tgl` for example, are
+/// marked in the disassembly.
+pub struct Debugger<'a> {
+    machine: &'a mut Machine,
+    sink: &'a mut dyn OutputSink,
+    initial: Vec<Instruction>,
+    breakpoints: Vec<Breakpoint>,
+    status: Status,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(machine: &'a mut Machine, sink: &'a mut dyn OutputSink) -> Debugger<'a> {
+        let initial = machine.instructions().to_vec();
+        let status = if machine.is_halted() {
+            Status::Halted
+        } else {
+            Status::Running
+        };
+
+        Debugger {
+            machine,
+            sink,
+            initial,
+            breakpoints: Vec::new(),
+            status,
+        }
+    }
+
+    /// Reads and runs commands until `quit` or the end of the input.
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        writeln!(output, "Type help for a list of commands")?;
+        self.show_current(&mut output)?;
+        write!(output, "(dbg) ")?;
+        output.flush()?;
+
+        for line in input.lines() {
+            let line = line?;
+            let words = line.split_whitespace().collect_vec();
+            if words.first().is_some_and(|w| *w == "quit" || *w == "q") {
+                break;
+            }
+
+            if let Err(message) = self.command(&words, &mut output)? {
+                writeln!(output, "{}", message)?;
+            }
+            write!(output, "(dbg) ")?;
+            output.flush()?;
+        }
+
+        writeln!(output)
+    }
+
+    /// Runs a single command. The outer result is for failing to write, the
+    /// inner one for commands that don't make sense.
+    fn command<W: Write>(
+        &mut self,
+        words: &[&str],
+        output: &mut W,
+    ) -> io::Result<Result<(), String>> {
+        match words {
+            [] => (),
+            ["step" | "s"] => self.run(Some(1), output)?,
+            ["step" | "s", count] => match count.parse() {
+                Ok(count) => self.run(Some(count), output)?,
+                Err(_) => return Ok(Err(format!("Invalid count: {}", count))),
+            },
+            ["continue" | "c"] => self.run(None, output)?,
+            ["break" | "b", pc] => match pc.parse() {
+                Ok(pc) => self.add_breakpoint(Breakpoint::Pc(pc), output)?,
+                Err(_) => return Ok(Err(format!("Invalid pc: {}", pc))),
+            },
+            ["break" | "b", reg, comparison, value] => {
+                match (
+                    parse_register(reg),
+                    Comparison::parse(comparison),
+                    value.parse(),
+                ) {
+                    (Some(reg), Some(comparison), Ok(value)) => {
+                        self.add_breakpoint(Breakpoint::Register(reg, comparison, value), output)?
+                    }
+                    _ => return Ok(Err("Expected break <reg> <op> <value>".to_string())),
+                }
+            }
+            ["delete", index] => match index.parse::<usize>() {
+                Ok(index) if index < self.breakpoints.len() => {
+                    let breakpoint = self.breakpoints.remove(index);
+                    writeln!(output, "Deleted breakpoint {}", breakpoint.describe())?;
+                }
+                _ => return Ok(Err(format!("No breakpoint {}", index))),
+            },
+            ["breakpoints"] => {
+                for (index, breakpoint) in self.breakpoints.iter().enumerate() {
+                    writeln!(output, "{}: {}", index, breakpoint.describe())?;
+                }
+            }
+            ["regs" | "r"] => self.show_registers(output)?,
+            ["set", reg, value] => match (parse_register(reg), value.parse()) {
+                (Some(_), Ok(value)) if !self.machine.width().contains(value) => {
+                    return Ok(Err(format!(
+                        "{} doesn't fit in {} registers",
+                        value,
+                        self.machine.width()
+                    )))
+                }
+                (Some(reg), Ok(value)) => {
+                    self.machine.set_register(reg, value);
+                    self.show_registers(output)?;
+                }
+                _ => return Ok(Err("Expected set <reg> <value>".to_string())),
+            },
+            ["disasm" | "d"] => self.disassemble(5, output)?,
+            ["disasm" | "d", radius] => match radius.parse() {
+                Ok(radius) => self.disassemble(radius, output)?,
+                Err(_) => return Ok(Err(format!("Invalid count: {}", radius))),
+            },
+            ["changes"] => {
+                for (pc, (before, after)) in self
+                    .initial
+                    .iter()
+                    .zip(self.machine.instructions())
+                    .enumerate()
+                    .filter(|(_, (before, after))| before != after)
+                {
+                    writeln!(output, "{}: {} -> {}", pc, before, after)?;
+                }
+            }
+            ["help" | "h"] => writeln!(output, "{}", HELP)?,
+            _ => return Ok(Err(format!("Unknown command: {}", words.join(" ")))),
+        }
+
+        Ok(Ok(()))
+    }
+
+    fn add_breakpoint<W: Write>(
+        &mut self,
+        breakpoint: Breakpoint,
+        output: &mut W,
+    ) -> io::Result<()> {
+        writeln!(
+            output,
+            "Breakpoint {}: {}",
+            self.breakpoints.len(),
+            breakpoint.describe()
+        )?;
+        self.breakpoints.push(breakpoint);
+        Ok(())
+    }
+
+    /// Steps `count` times, or until a breakpoint when there's no count. A pc
+    /// breakpoint stops before its instruction, though not the one we start
+    /// on, and a register breakpoint stops once its condition becomes true.
+    fn run<W: Write>(&mut self, count: Option<u64>, output: &mut W) -> io::Result<()> {
+        if self.status != Status::Running {
+            return writeln!(output, "The program has {}", self.status_name());
+        }
+
+        let mut steps = 0;
+        while count.is_none_or(|count| steps < count) {
+            if steps > 0 {
+                if let Some(breakpoint) = self
+                    .breakpoints
+                    .iter()
+                    .find(|b| matches!(b, Breakpoint::Pc(_)) && b.hit(self.machine))
+                {
+                    writeln!(output, "Hit breakpoint {}", breakpoint.describe())?;
+                    break;
+                }
+            }
+
+            let before = self
+                .breakpoints
+                .iter()
+                .map(|b| b.hit(self.machine))
+                .collect_vec();
+            self.status = self.machine.step(self.sink);
+            steps += 1;
+            if self.status != Status::Running {
+                writeln!(output, "The program has {}", self.status_name())?;
+                break;
+            }
+
+            if let Some(breakpoint) = self
+                .breakpoints
+                .iter()
+                .zip(before)
+                .find(|(b, was_hit)| {
+                    matches!(b, Breakpoint::Register(..)) && !was_hit && b.hit(self.machine)
+                })
+                .map(|(b, _)| b)
+            {
+                writeln!(output, "Hit breakpoint {}", breakpoint.describe())?;
+                break;
+            }
+        }
+
+        self.show_current(output)
+    }
+
+    fn status_name(&self) -> &'static str {
+        match self.status {
+            Status::Running => "not finished",
+            Status::Halted => "halted",
+            Status::Stopped => "been stopped by its output",
+            Status::Overflowed => "overflowed a register",
+        }
+    }
+
+    fn show_current<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        match self.machine.current() {
+            Some(instruction) => writeln!(output, "{}: {}", self.machine.pc(), instruction)?,
+            None => writeln!(output, "{}: <outside the program>", self.machine.pc())?,
+        }
+        self.show_registers(output)
+    }
+
+    fn show_registers<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        writeln!(
+            output,
+            "{}  steps={}",
+            Register::ALL
+                .iter()
+                .map(|reg| format!("{}={}", reg, self.machine.register(*reg)))
+                .join(" "),
+            self.machine.steps()
+        )
+    }
+
+    fn disassemble<W: Write>(&self, radius: i64, output: &mut W) -> io::Result<()> {
+        let instructions = self.machine.instructions();
+        let pc = self.machine.pc();
+        let first = (pc - radius).max(0);
+        let last = (pc + radius).min(instructions.len() as i64 - 1);
+
+        for index in first..=last {
+            let instruction = &instructions[index as usize];
+            let changed = self.initial[index as usize] != *instruction;
+            writeln!(
+                output,
+                "{}{}{:>4}: {}{}",
+                if index == pc { ">" } else { " " },
+                if changed { "*" } else { " " },
+                index,
+                instruction,
+                if changed {
+                    format!("    (was {})", self.initial[index as usize])
+                } else {
+                    String::new()
+                }
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+use crate::instruction::{Instruction, Operand};
+use std::collections::BTreeSet;
+
+/// Turns a program into structured pseudo-code. Backward `jnz` jumps with a
+/// constant offset become loops and forward ones become `if` blocks, anything
+/// that doesn't nest falls back to a `goto`. Run the optimizer first to see
+/// the addition and multiplication loops as `+=` and `*`.
+///
+/// `tgl` makes the program rewrite itself, so the instructions it could reach
+/// are marked unsafe: the decompiled code there may not be what runs. A `tgl`
+/// with a constant offset only reaches one instruction. One with a register
+/// offset could reach anywhere, so everything after it is assumed to be a
+/// target, which is how the puzzle programs use it.
+pub fn decompile(instructions: &[Instruction]) -> String {
+    let mut decompiler = Decompiler {
+        instructions,
+        unsafe_from: toggle_targets(instructions),
+        labels: BTreeSet::new(),
+        placed: BTreeSet::new(),
+        lines: Vec::new(),
+    };
+
+    // The first pass finds which gotos need labels, the second uses them
+    decompiler.block(0, instructions.len(), 0);
+    decompiler.lines.clear();
+    decompiler.placed.clear();
+    decompiler.block(0, instructions.len(), 0);
+
+    let mut output = String::new();
+    for (pc, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::Toggle(Operand::Value(offset)) => {
+                output += &format!(
+                    "// tgl at {} rewrites pc + {}, which is marked unsafe\n",
+                    pc, offset
+                );
+            }
+            Instruction::Toggle(Operand::Register(reg)) => {
+                output += &format!(
+                    "// tgl at {} rewrites pc + {}, everything after it is marked unsafe\n",
+                    pc, reg
+                );
+            }
+            _ => (),
+        }
+    }
+    for line in decompiler.lines {
+        output += &line;
+        output.push('\n');
+    }
+    output
+}
+
+/// For each instruction, the `tgl` that might rewrite it.
+fn toggle_targets(instructions: &[Instruction]) -> Vec<Option<usize>> {
+    let mut targets = vec![None; instructions.len()];
+    for (pc, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::Toggle(Operand::Value(offset)) => {
+                let target = pc as i32 + offset;
+                if let Some(target) = usize::try_from(target)
+                    .ok()
+                    .and_then(|target| targets.get_mut(target))
+                {
+                    target.get_or_insert(pc);
+                }
+            }
+            Instruction::Toggle(Operand::Register(_)) => {
+                for target in targets.iter_mut().skip(pc + 1) {
+                    target.get_or_insert(pc);
+                }
+            }
+            _ => (),
+        }
+    }
+    targets
+}
+
+struct Decompiler<'a> {
+    instructions: &'a [Instruction],
+    unsafe_from: Vec<Option<usize>>,
+    labels: BTreeSet<usize>,
+    // Labels already written out, a loop header is visited more than once
+    placed: BTreeSet<usize>,
+    lines: Vec<String>,
+}
+
+impl Decompiler<'_> {
+    /// The target of a `jnz` with a constant offset that can jump.
+    fn jump_target(&self, pc: usize) -> Option<usize> {
+        match self.instructions[pc] {
+            Instruction::JumpNotZero(Operand::Value(0), _) => None,
+            Instruction::JumpNotZero(_, Operand::Value(offset)) => {
+                let target = pc as i32 + offset;
+                (target >= 0 && target as usize <= self.instructions.len())
+                    .then_some(target as usize)
+            }
+            _ => None,
+        }
+    }
+
+    fn emit(&mut self, pc: Option<usize>, depth: usize, text: String) {
+        let gutter = match pc {
+            Some(pc) => format!("{:>4}", pc),
+            None => "    ".to_string(),
+        };
+        let note = match pc.and_then(|pc| self.unsafe_from[pc]) {
+            Some(tgl) => format!("    // unsafe: tgl at {}", tgl),
+            None => String::new(),
+        };
+        self.lines.push(format!(
+            "{}  {}{}{}",
+            gutter,
+            "    ".repeat(depth),
+            text,
+            note
+        ));
+    }
+
+    /// Decompiles `start..end`, which has to be entered from `start`.
+    fn block(&mut self, start: usize, end: usize, depth: usize) {
+        let mut pc = start;
+        while pc < end {
+            if self.labels.contains(&pc) && self.placed.insert(pc) {
+                self.emit(None, depth, format!("L{}:", pc));
+            }
+
+            // The outermost loop starting here that fits in this block
+            let loop_end = (pc..end)
+                .rev()
+                .find(|&j| j > pc && self.jump_target(j) == Some(pc));
+            if let Some(loop_end) = loop_end {
+                self.emit(None, depth, "loop {".to_string());
+                self.block(pc, loop_end, depth + 1);
+                if let Instruction::JumpNotZero(Operand::Register(reg), _) =
+                    self.instructions[loop_end]
+                {
+                    self.emit(
+                        Some(loop_end),
+                        depth + 1,
+                        format!("if {} == 0 {{ break }}", reg),
+                    );
+                }
+                self.emit(None, depth, "}".to_string());
+                pc = loop_end + 1;
+                continue;
+            }
+
+            match self.instructions[pc] {
+                Instruction::JumpNotZero(test, offset) => {
+                    let target = self.jump_target(pc);
+                    match (test, target) {
+                        (Operand::Value(0), _) => {}
+                        // Skipping forward over a block that nests
+                        (Operand::Register(reg), Some(target))
+                            if target > pc + 1 && target <= end =>
+                        {
+                            self.emit(Some(pc), depth, format!("if {} == 0 {{", reg));
+                            self.block(pc + 1, target, depth + 1);
+                            self.emit(None, depth, "}".to_string());
+                            pc = target;
+                            continue;
+                        }
+                        (Operand::Value(_), Some(target)) => {
+                            self.labels.insert(target);
+                            self.emit(Some(pc), depth, format!("goto L{}", target));
+                        }
+                        (Operand::Register(reg), Some(target)) => {
+                            self.labels.insert(target);
+                            self.emit(
+                                Some(pc),
+                                depth,
+                                format!("if {} != 0 {{ goto L{} }}", reg, target),
+                            );
+                        }
+                        (Operand::Value(_), None) => {
+                            self.emit(Some(pc), depth, format!("goto pc + {}", offset))
+                        }
+                        (Operand::Register(reg), None) => self.emit(
+                            Some(pc),
+                            depth,
+                            format!("if {} != 0 {{ goto pc + {} }}", reg, offset),
+                        ),
+                    }
+                }
+                instruction => {
+                    if let Some(text) = statement(&instruction) {
+                        self.emit(Some(pc), depth, text);
+                    }
+                }
+            }
+
+            pc += 1;
+        }
+
+        if end == self.instructions.len() && self.labels.contains(&end) && self.placed.insert(end) {
+            self.emit(None, depth, format!("L{}:", end));
+        }
+    }
+}
+
+fn statement(instruction: &Instruction) -> Option<String> {
+    let text = match instruction {
+        _ if !instruction.is_valid() => format!("// skipped: {}", instruction),
+        Instruction::Nop => return None,
+        Instruction::Add(src, dst) => format!("{} += {}", dst, src),
+        Instruction::Copy(src, dst) => format!("{} = {}", dst, src),
+        Instruction::Increment(dst) => format!("{} += 1", dst),
+        Instruction::Decrement(dst) => format!("{} -= 1", dst),
+        Instruction::JumpNotZero(_, _) => unreachable!("Jumps are structured separately"),
+        Instruction::Toggle(offset) => format!("toggle(pc + {})", offset),
+        Instruction::Out(src) => format!("out({})", src),
+        Instruction::Multiply(x, y, dst) => format!("{} += {} * {}", dst, x, y),
+    };
+    Some(text)
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(usize)]
+pub enum Register {
+    A = 0,
+    B,
+    C,
+    D,
+}
+
+impl Register {
+    pub const ALL: [Register; 4] = [Register::A, Register::B, Register::C, Register::D];
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Register::A => "a",
+            Register::B => "b",
+            Register::C => "c",
+            Register::D => "d",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Operand {
+    Value(i32),
+    Register(Register),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Value(value) => write!(f, "{}", value),
+            Operand::Register(reg) => write!(f, "{}", reg),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    Nop,
+    Add(Operand, Operand),
+    Copy(Operand, Operand),
+    Increment(Operand),
+    Decrement(Operand),
+    JumpNotZero(Operand, Operand),
+    Toggle(Operand),
+    Out(Operand),
+    /// `mul x y z` adds `x * y` to `z`
+    Multiply(Operand, Operand, Operand),
+}
+
+impl Instruction {
+    /// The arguments, in source order.
+    pub fn operands(&self) -> Vec<Operand> {
+        match *self {
+            Instruction::Nop => vec![],
+            Instruction::Increment(x)
+            | Instruction::Decrement(x)
+            | Instruction::Toggle(x)
+            | Instruction::Out(x) => vec![x],
+            Instruction::Add(x, y) | Instruction::Copy(x, y) | Instruction::JumpNotZero(x, y) => {
+                vec![x, y]
+            }
+            Instruction::Multiply(x, y, z) => vec![x, y, z],
+        }
+    }
+
+    /// The instruction `tgl` turns this one into. The rewrite only depends
+    /// on how many arguments there are, and the arguments are kept as they
+    /// are:
+    ///
+    /// | arguments | instruction         | becomes |
+    /// |-----------|---------------------|---------|
+    /// | 0         | `nop`               | itself  |
+    /// | 1         | `inc`               | `dec`   |
+    /// | 1         | `dec`, `tgl`, `out` | `inc`   |
+    /// | 2         | `jnz`               | `cpy`   |
+    /// | 2         | `cpy`, `add`        | `jnz`   |
+    /// | 3         | `mul`               | itself  |
+    ///
+    /// The puzzle only describes one and two arguments. `nop` and `mul` come
+    /// from the optimizer, which puts the original loop back before a `tgl`
+    /// reaches it, so leaving them alone only matters for hand-written code.
+    ///
+    /// The result can be invalid, `cpy 1 2` from `jnz 1 2` for example. See
+    /// [`Instruction::is_valid`].
+    pub fn toggled(&self) -> Instruction {
+        match (self, &self.operands()[..]) {
+            (Instruction::Increment(_), &[x]) => Instruction::Decrement(x),
+            (_, &[x]) => Instruction::Increment(x),
+            (Instruction::JumpNotZero(_, _), &[x, y]) => Instruction::Copy(x, y),
+            (_, &[x, y]) => Instruction::JumpNotZero(x, y),
+            _ => *self,
+        }
+    }
+
+    /// The register this instruction writes to, if it writes to one.
+    /// `Some(Operand::Value(_))` means it would write to an immediate.
+    pub fn destination(&self) -> Option<Operand> {
+        match *self {
+            Instruction::Add(_, dst)
+            | Instruction::Copy(_, dst)
+            | Instruction::Increment(dst)
+            | Instruction::Decrement(dst)
+            | Instruction::Multiply(_, _, dst) => Some(dst),
+            Instruction::Nop
+            | Instruction::JumpNotZero(_, _)
+            | Instruction::Toggle(_)
+            | Instruction::Out(_) => None,
+        }
+    }
+
+    /// Whether the instruction can run. The only invalid instructions are
+    /// ones that write to an immediate, which `tgl` can produce, and the
+    /// machine skips them as if they were a `nop`.
+    pub fn is_valid(&self) -> bool {
+        !matches!(self.destination(), Some(Operand::Value(_)))
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Nop => write!(f, "nop"),
+            Instruction::Add(src, dst) => write!(f, "add {} {}", src, dst),
+            Instruction::Copy(src, dst) => write!(f, "cpy {} {}", src, dst),
+            Instruction::Increment(dst) => write!(f, "inc {}", dst),
+            Instruction::Decrement(dst) => write!(f, "dec {}", dst),
+            Instruction::JumpNotZero(test, offset) => write!(f, "jnz {} {}", test, offset),
+            Instruction::Toggle(offset) => write!(f, "tgl {}", offset),
+            Instruction::Out(src) => write!(f, "out {}", src),
+            Instruction::Multiply(x, y, dst) => write!(f, "mul {} {} {}", x, y, dst),
+        }
+    }
+}
@@ -0,0 +1,23 @@
+//! The assembunny instruction set and interpreter shared by day12, day23 and
+//! day25.
+
+pub mod cli;
+mod debugger;
+mod decompiler;
+mod instruction;
+mod machine;
+mod optimizer;
+mod parser;
+mod profiler;
+mod trace;
+mod transpiler;
+
+pub use debugger::Debugger;
+pub use decompiler::decompile;
+pub use instruction::{Instruction, Operand, Register};
+pub use machine::{Discard, Machine, OutputSink, Status, Stdout, Width};
+pub use optimizer::{optimize, Region};
+pub use parser::{load_program, parse_program, ParseError};
+pub use profiler::Profile;
+pub use trace::{TraceReader, TraceStep, TraceWriter};
+pub use transpiler::{build_directory, transpile, Native, NativeExit};
@@ -0,0 +1,304 @@
+use crate::{
+    instruction::{Instruction, Operand, Register},
+    optimizer::{self, Region},
+};
+use std::{fmt, ops::ControlFlow};
+
+/// Somewhere for `out` to send its values. Returning `ControlFlow::Break`
+/// stops the machine after the current instruction.
+pub trait OutputSink {
+    fn output(&mut self, value: i64) -> ControlFlow<()>;
+}
+
+/// Prints each value on its own line.
+pub struct Stdout;
+
+impl OutputSink for Stdout {
+    fn output(&mut self, value: i64) -> ControlFlow<()> {
+        println!("{}", value);
+        ControlFlow::Continue(())
+    }
+}
+
+/// Throws every value away.
+pub struct Discard;
+
+impl OutputSink for Discard {
+    fn output(&mut self, _value: i64) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+}
+
+impl OutputSink for Vec<i64> {
+    fn output(&mut self, value: i64) -> ControlFlow<()> {
+        self.push(value);
+        ControlFlow::Continue(())
+    }
+}
+
+impl<F: FnMut(i64) -> ControlFlow<()>> OutputSink for F {
+    fn output(&mut self, value: i64) -> ControlFlow<()> {
+        self(value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Running,
+    /// The program counter left the program
+    Halted,
+    /// The output sink asked to stop
+    Stopped,
+    /// The instruction at pc overflowed a register and wasn't run
+    Overflowed,
+}
+
+/// How wide the registers are. The puzzles assume `i32`, `i64` leaves room
+/// for larger inputs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Width {
+    #[default]
+    I32,
+    I64,
+}
+
+impl Width {
+    /// Whether a register this wide can hold `value`.
+    pub fn contains(self, value: i64) -> bool {
+        match self {
+            Width::I32 => i32::try_from(value).is_ok(),
+            Width::I64 => true,
+        }
+    }
+
+    /// The largest value a register this wide can hold.
+    pub fn max(self) -> i64 {
+        match self {
+            Width::I32 => i32::MAX as i64,
+            Width::I64 => i64::MAX,
+        }
+    }
+}
+
+impl fmt::Display for Width {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Width::I32 => write!(f, "i32"),
+            Width::I64 => write!(f, "i64"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Machine {
+    instructions: Vec<Instruction>,
+    registers: [i64; 4],
+    pc: i64,
+    steps: u64,
+    regions: Vec<Region>,
+    width: Width,
+}
+
+impl Machine {
+    pub fn new(instructions: Vec<Instruction>) -> Machine {
+        Machine {
+            instructions,
+            registers: [0; 4],
+            pc: 0,
+            steps: 0,
+            regions: Vec::new(),
+            width: Width::default(),
+        }
+    }
+
+    /// Rewrites the program's addition and multiplication loops into `add`
+    /// and `mul`. A rewritten loop is put back the way it was if `tgl` touches
+    /// it, something jumps into the middle of it, or it's entered with a
+    /// counter that isn't positive.
+    pub fn optimize(&mut self) {
+        let (instructions, regions) = optimizer::optimize(&self.instructions);
+        self.instructions = instructions;
+        self.regions = regions;
+    }
+
+    /// Puts back the original instructions for the rewritten loop covering
+    /// `index`, if there is one.
+    fn deoptimize(&mut self, index: usize) {
+        if let Some(position) = self.regions.iter().position(|r| r.contains(index)) {
+            let region = self.regions.remove(position);
+            self.instructions[region.start..region.start + region.original.len()]
+                .copy_from_slice(&region.original);
+        }
+    }
+
+    pub fn width(&self) -> Width {
+        self.width
+    }
+
+    pub fn set_width(&mut self, width: Width) {
+        self.width = width;
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    pub fn registers(&self) -> &[i64; 4] {
+        &self.registers
+    }
+
+    pub fn register(&self, reg: Register) -> i64 {
+        self.registers[reg as usize]
+    }
+
+    pub fn set_register(&mut self, reg: Register, value: i64) {
+        self.registers[reg as usize] = value;
+    }
+
+    pub fn pc(&self) -> i64 {
+        self.pc
+    }
+
+    /// How many instructions have been executed.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.pc < 0 || self.pc as usize >= self.instructions.len()
+    }
+
+    /// The instruction about to run, if the program hasn't halted.
+    pub fn current(&self) -> Option<&Instruction> {
+        if self.is_halted() {
+            None
+        } else {
+            Some(&self.instructions[self.pc as usize])
+        }
+    }
+
+    fn value(&self, operand: &Operand) -> i64 {
+        match operand {
+            Operand::Value(val) => *val as i64,
+            Operand::Register(reg) => self.registers[*reg as usize],
+        }
+    }
+
+    /// The instruction the next step will run, if the program hasn't halted.
+    /// A rewritten loop entered with a counter that isn't positive is put
+    /// back first, so this can differ from [`Machine::current`] before it.
+    pub fn fetch(&mut self) -> Option<Instruction> {
+        let instruction = self.current().copied()?;
+        if matches!(
+            instruction,
+            Instruction::Add(..) | Instruction::Multiply(..)
+        ) {
+            let pc = self.pc as usize;
+            let unguarded = self.regions.iter().any(|r| {
+                r.start == pc && r.positive.iter().any(|operand| self.value(operand) <= 0)
+            });
+            if unguarded {
+                self.deoptimize(pc);
+                return Some(self.instructions[pc]);
+            }
+        }
+        Some(instruction)
+    }
+
+    /// Runs a single instruction. One that would overflow a register isn't
+    /// run, and leaves the machine as it was.
+    pub fn step(&mut self, sink: &mut dyn OutputSink) -> Status {
+        let Some(instruction) = self.fetch() else {
+            return Status::Halted;
+        };
+
+        let mut status = Status::Running;
+        // The register to write and its new value, or None if that overflows
+        let mut write = None;
+        // Anything writing to an immediate is skipped, see Instruction::is_valid
+        let destination = |operand: &Operand| match operand {
+            Operand::Register(reg) => *reg as usize,
+            Operand::Value(_) => unreachable!("Invalid instructions are skipped"),
+        };
+        match &instruction {
+            _ if !instruction.is_valid() => (),
+            Instruction::Nop => (),
+            Instruction::Add(src, dst) => {
+                write = Some((
+                    destination(dst),
+                    self.value(dst).checked_add(self.value(src)),
+                ))
+            }
+            Instruction::Copy(src, dst) => write = Some((destination(dst), Some(self.value(src)))),
+            Instruction::Increment(dst) => {
+                write = Some((destination(dst), self.value(dst).checked_add(1)))
+            }
+            Instruction::Decrement(dst) => {
+                write = Some((destination(dst), self.value(dst).checked_sub(1)))
+            }
+            Instruction::JumpNotZero(test, offset) => {
+                if self.value(test) != 0 {
+                    let target = self.pc.saturating_add(self.value(offset));
+                    if target >= 0
+                        && self
+                            .regions
+                            .iter()
+                            .any(|r| r.contains(target as usize) && r.start != target as usize)
+                    {
+                        self.deoptimize(target as usize);
+                    }
+
+                    // To offset the += 1 below
+                    self.pc = target.saturating_sub(1);
+                }
+            }
+            Instruction::Toggle(offset) => {
+                let target = self.pc.saturating_add(self.value(offset));
+                // Do nothing if we're not pointing to an instruction in the program
+                if target >= 0 && (target as usize) < self.instructions.len() {
+                    let target = target as usize;
+                    self.deoptimize(target);
+                    self.instructions[target] = self.instructions[target].toggled();
+                }
+            }
+            Instruction::Multiply(x, y, dst) => {
+                let product = self.value(x).checked_mul(self.value(y));
+                write = Some((
+                    destination(dst),
+                    product.and_then(|product| self.value(dst).checked_add(product)),
+                ))
+            }
+            Instruction::Out(src) => {
+                if sink.output(self.value(src)).is_break() {
+                    status = Status::Stopped;
+                }
+            }
+        }
+
+        if let Some((reg, value)) = write {
+            match value.filter(|value| self.width.contains(*value)) {
+                Some(value) => self.registers[reg] = value,
+                None => return Status::Overflowed,
+            }
+        }
+
+        self.pc += 1;
+        self.steps += 1;
+
+        if status == Status::Running && self.is_halted() {
+            Status::Halted
+        } else {
+            status
+        }
+    }
+
+    /// Runs until the program halts or the sink stops it.
+    pub fn run(&mut self, sink: &mut dyn OutputSink) -> Status {
+        loop {
+            match self.step(sink) {
+                Status::Running => (),
+                status => return status,
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use crate::instruction::{Instruction, Operand};
+
+/// A stretch of the program the optimizer rewrote. The rewrite is the same
+/// length as the original so jump offsets around it still line up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub start: usize,
+    pub original: Vec<Instruction>,
+    /// The rewrite only matches the loop when these are positive on entry,
+    /// otherwise the loop would count down through zero.
+    pub positive: Vec<Operand>,
+}
+
+impl Region {
+    pub fn contains(&self, index: usize) -> bool {
+        index >= self.start && index < self.start + self.original.len()
+    }
+}
+
+/// Rewrites the addition and multiplication loops into `add` and `mul`,
+/// returning the new program and the regions that were replaced.
+pub fn optimize(instructions: &[Instruction]) -> (Vec<Instruction>, Vec<Region>) {
+    let mut optimized = instructions.to_vec();
+    let mut regions = Vec::new();
+
+    let mut index = 0;
+    while index < instructions.len() {
+        let rest = &instructions[index..];
+        let rewrite = multiply_loop(rest).or_else(|| add_loop(rest));
+
+        match rewrite {
+            Some((replacement, positive)) => {
+                let len = replacement.len();
+                optimized[index..index + len].copy_from_slice(&replacement);
+                regions.push(Region {
+                    start: index,
+                    original: instructions[index..index + len].to_vec(),
+                    positive,
+                });
+                index += len;
+            }
+            None => index += 1,
+        }
+    }
+
+    (optimized, regions)
+}
+
+/// ```text
+/// inc x
+/// dec y
+/// jnz y -2
+/// ```
+///
+/// becomes `add y x; cpy 0 y; nop`. The `inc` and `dec` can be either way
+/// round.
+fn add_loop(instructions: &[Instruction]) -> Option<(Vec<Instruction>, Vec<Operand>)> {
+    let (x, y) = match instructions {
+        [Instruction::Increment(Operand::Register(x)), Instruction::Decrement(Operand::Register(y)), Instruction::JumpNotZero(Operand::Register(test), Operand::Value(-2)), ..]
+        | [Instruction::Decrement(Operand::Register(y)), Instruction::Increment(Operand::Register(x)), Instruction::JumpNotZero(Operand::Register(test), Operand::Value(-2)), ..]
+            if test == y && x != y =>
+        {
+            (*x, *y)
+        }
+        _ => return None,
+    };
+
+    Some((
+        vec![
+            Instruction::Add(Operand::Register(y), Operand::Register(x)),
+            Instruction::Copy(Operand::Value(0), Operand::Register(y)),
+            Instruction::Nop,
+        ],
+        vec![Operand::Register(y)],
+    ))
+}
+
+/// ```text
+/// cpy b c
+/// inc a
+/// dec c
+/// jnz c -2
+/// dec d
+/// jnz d -5
+/// ```
+///
+/// becomes `mul b d a; cpy 0 c; cpy 0 d; nop; nop; nop`.
+fn multiply_loop(instructions: &[Instruction]) -> Option<(Vec<Instruction>, Vec<Operand>)> {
+    let [Instruction::Copy(b, Operand::Register(c)), ref inner @ .., Instruction::Decrement(Operand::Register(d)), Instruction::JumpNotZero(Operand::Register(test), Operand::Value(-5))] =
+        *instructions.get(..6)?
+    else {
+        return None;
+    };
+
+    // The inner loop has to be the addition loop counting down `c`
+    let (a, counter) = match add_loop(inner)?.0[0] {
+        Instruction::Add(Operand::Register(y), Operand::Register(x)) => (x, y),
+        _ => return None,
+    };
+    if counter != c || test != d {
+        return None;
+    }
+
+    // Every register involved has to be distinct or the loops interact
+    if a == d
+        || c == d
+        || b == Operand::Register(a)
+        || b == Operand::Register(c)
+        || b == Operand::Register(d)
+    {
+        return None;
+    }
+
+    Some((
+        vec![
+            Instruction::Multiply(b, Operand::Register(d), Operand::Register(a)),
+            Instruction::Copy(Operand::Value(0), Operand::Register(c)),
+            Instruction::Copy(Operand::Value(0), Operand::Register(d)),
+            Instruction::Nop,
+            Instruction::Nop,
+            Instruction::Nop,
+        ],
+        vec![b, Operand::Register(d)],
+    ))
+}
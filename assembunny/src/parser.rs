@@ -0,0 +1,209 @@
+use crate::instruction::{Instruction, Operand, Register};
+use std::{collections::HashMap, fmt, fs};
+
+/// A problem in assembunny source, pointing at the offending token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub file: String,
+    /// 1-based, or 0 if the file couldn't be read at all
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(
+                f,
+                "{}:{}:{}: {}",
+                self.file, self.line, self.column, self.message
+            )
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A word from the source and where it starts.
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+/// Reads and parses a program from a file.
+pub fn load_program(path: &str) -> Result<Vec<Instruction>, ParseError> {
+    let source = fs::read_to_string(path).map_err(|error| ParseError {
+        file: path.to_string(),
+        line: 0,
+        column: 0,
+        message: format!("Failed to read file: {}", error),
+    })?;
+    parse_program(&source, path)
+}
+
+/// Parses assembunny source. Besides one instruction per line it accepts
+/// blank lines, comments starting with `;` and labels:
+///
+/// ```text
+/// loop_start:   ; a label on its own line
+///     inc a
+///     dec b
+///     jnz b loop_start
+/// done: nop     ; or in front of an instruction
+/// ```
+///
+/// A label used as a `jnz` or `tgl` offset is turned into the relative offset
+/// to the instruction it names. `file` is only used in errors.
+pub fn parse_program(source: &str, file: &str) -> Result<Vec<Instruction>, ParseError> {
+    let error = |token: &Token, message: String| ParseError {
+        file: file.to_string(),
+        line: token.line,
+        column: token.column,
+        message,
+    };
+
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    let mut statements = Vec::new();
+
+    for (number, line) in source.lines().enumerate() {
+        let code = line.split(';').next().unwrap_or_default();
+        let mut tokens = tokenize(code, number + 1);
+
+        while let Some(label) = tokens.first().and_then(|t| t.text.strip_suffix(':')) {
+            let token = tokens.remove(0);
+            if !is_identifier(label) || parse_register(label).is_some() {
+                return Err(error(&token, format!("Invalid label name \"{}\"", label)));
+            }
+            if labels.insert(label, statements.len()).is_some() {
+                return Err(error(
+                    &token,
+                    format!("Label \"{}\" is defined twice", label),
+                ));
+            }
+        }
+
+        if !tokens.is_empty() {
+            statements.push(tokens);
+        }
+    }
+
+    let register = |token: &Token| {
+        parse_register(token.text).ok_or_else(|| {
+            error(
+                token,
+                format!("Expected a register, got \"{}\"", token.text),
+            )
+        })
+    };
+    let operand = |token: &Token| match token.text.parse::<i32>() {
+        Ok(value) => Ok(Operand::Value(value)),
+        Err(_) => register(token).map(Operand::Register),
+    };
+    // A jump or toggle offset can also be a label, relative to the instruction
+    let offset = |token: &Token, pc: usize| match operand(token) {
+        Ok(operand) => Ok(operand),
+        Err(_) if is_identifier(token.text) => labels
+            .get(token.text)
+            .map(|target| Operand::Value(*target as i32 - pc as i32))
+            .ok_or_else(|| error(token, format!("Undefined label \"{}\"", token.text))),
+        Err(_) => Err(error(
+            token,
+            format!(
+                "Expected a register, number or label, got \"{}\"",
+                token.text
+            ),
+        )),
+    };
+
+    statements
+        .iter()
+        .enumerate()
+        .map(|(pc, tokens)| {
+            let mnemonic = &tokens[0];
+            let arguments = &tokens[1..];
+            let arity = match mnemonic.text {
+                "nop" => 0,
+                "inc" | "dec" | "tgl" | "out" => 1,
+                "add" | "cpy" | "jnz" => 2,
+                "mul" => 3,
+                _ => {
+                    return Err(error(
+                        mnemonic,
+                        format!("Unexpected instruction \"{}\"", mnemonic.text),
+                    ))
+                }
+            };
+            if arguments.len() != arity {
+                let token = arguments.get(arity).unwrap_or(mnemonic);
+                return Err(error(
+                    token,
+                    format!(
+                        "{} takes {} operand{}, got {}",
+                        mnemonic.text,
+                        arity,
+                        if arity == 1 { "" } else { "s" },
+                        arguments.len()
+                    ),
+                ));
+            }
+
+            Ok(match mnemonic.text {
+                "nop" => Instruction::Nop,
+                "add" => Instruction::Add(operand(&arguments[0])?, operand(&arguments[1])?),
+                "cpy" => Instruction::Copy(operand(&arguments[0])?, operand(&arguments[1])?),
+                "inc" => Instruction::Increment(Operand::Register(register(&arguments[0])?)),
+                "dec" => Instruction::Decrement(Operand::Register(register(&arguments[0])?)),
+                "jnz" => {
+                    Instruction::JumpNotZero(operand(&arguments[0])?, offset(&arguments[1], pc)?)
+                }
+                "tgl" => Instruction::Toggle(offset(&arguments[0], pc)?),
+                "out" => Instruction::Out(operand(&arguments[0])?),
+                "mul" => Instruction::Multiply(
+                    operand(&arguments[0])?,
+                    operand(&arguments[1])?,
+                    operand(&arguments[2])?,
+                ),
+                _ => unreachable!("Arity already checked the mnemonic"),
+            })
+        })
+        .collect()
+}
+
+fn tokenize(code: &str, line: usize) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (index, c) in code.char_indices().chain([(code.len(), ' ')]) {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(index),
+            (true, Some(begin)) => {
+                tokens.push(Token {
+                    text: &code[begin..index],
+                    line,
+                    column: code[..begin].chars().count() + 1,
+                });
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    tokens
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+pub(crate) fn parse_register(text: &str) -> Option<Register> {
+    Register::ALL
+        .into_iter()
+        .find(|reg| reg.to_string() == text)
+}
@@ -0,0 +1,108 @@
+use crate::{
+    instruction::Instruction,
+    machine::{Machine, OutputSink, Status},
+};
+use std::collections::HashMap;
+
+/// How many loops the report lists.
+const HOTTEST: usize = 10;
+
+/// Counts how often each instruction runs and how often each backward jump
+/// is taken, a backward jump being the end of a loop.
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    hits: Vec<u64>,
+    /// (jump pc, target pc) to times taken
+    loops: HashMap<(usize, usize), u64>,
+    total: u64,
+}
+
+impl Profile {
+    pub fn new(machine: &Machine) -> Profile {
+        Profile {
+            hits: vec![0; machine.instructions().len()],
+            ..Profile::default()
+        }
+    }
+
+    /// Steps the machine, counting the instruction it runs.
+    pub fn step(&mut self, machine: &mut Machine, sink: &mut dyn OutputSink) -> Status {
+        let pc = machine.pc();
+        let is_jump = matches!(machine.fetch(), Some(Instruction::JumpNotZero(..)));
+        let status = machine.step(sink);
+
+        if let Some(hits) = usize::try_from(pc)
+            .ok()
+            .and_then(|pc| self.hits.get_mut(pc))
+        {
+            *hits += 1;
+            self.total += 1;
+            if is_jump && machine.pc() <= pc && machine.pc() >= 0 {
+                *self
+                    .loops
+                    .entry((pc as usize, machine.pc() as usize))
+                    .or_default() += 1;
+            }
+        }
+        status
+    }
+
+    /// Adds the counts from another run of the same program.
+    pub fn merge(&mut self, other: &Profile) {
+        if self.hits.len() < other.hits.len() {
+            self.hits.resize(other.hits.len(), 0);
+        }
+        for (hits, other) in self.hits.iter_mut().zip(other.hits.iter()) {
+            *hits += other;
+        }
+        for (jump, count) in other.loops.iter() {
+            *self.loops.entry(*jump).or_default() += count;
+        }
+        self.total += other.total;
+    }
+
+    /// The summary and the program listing with the counts next to each
+    /// instruction. `instructions` is the program as it ended up, after any
+    /// `tgl`, so an instruction's count can be partly from what it was before.
+    pub fn report(&self, instructions: &[Instruction]) -> String {
+        let mut output = format!("Executed {} instructions\n", self.total);
+
+        let mut loops = self.loops.iter().collect::<Vec<_>>();
+        loops.sort_by_key(|((jump, target), count)| (std::cmp::Reverse(**count), *jump, *target));
+        if !loops.is_empty() {
+            output += "\nHottest loops, by times jumped back:\n";
+        }
+        for ((jump, target), count) in loops.into_iter().take(HOTTEST) {
+            let body = self.hits[*target..=*jump].iter().sum::<u64>();
+            output += &format!(
+                "{:>12}  pc {}..={}, {} instructions run inside ({:.1}%)\n",
+                count,
+                target,
+                jump,
+                body,
+                percentage(body, self.total)
+            );
+        }
+
+        output += "\n        hits       %    pc\n";
+        for (pc, instruction) in instructions.iter().enumerate() {
+            let hits = self.hits.get(pc).copied().unwrap_or_default();
+            output += &format!(
+                "{:>12}  {:>5.1}%  {:>4}: {}\n",
+                hits,
+                percentage(hits, self.total),
+                pc,
+                instruction
+            );
+        }
+        output
+    }
+}
+
+fn percentage(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
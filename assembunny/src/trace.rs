@@ -0,0 +1,243 @@
+//! A compact binary record of a run, one entry per instruction executed.
+//!
+//! The file starts with `BUNT`, a version byte and the four starting
+//! registers. Each step is then:
+//!
+//! - the pc
+//! - a byte with the opcode in the high nibble and a bit per register that
+//!   changed in the low one
+//! - the instruction's operands, a byte each: 0-3 for a register, 4 followed
+//!   by the value for an immediate
+//! - how much each changed register went up by, in register order
+//!
+//! Numbers are zigzag varints, so small ones of either sign take a byte.
+
+use crate::instruction::{Instruction, Operand, Register};
+use std::io::{self, ErrorKind, Read, Write};
+
+const MAGIC: &[u8; 4] = b"BUNT";
+const VERSION: u8 = 1;
+const IMMEDIATE: u8 = 4;
+
+/// One step read back from a trace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceStep {
+    /// Counting from 1
+    pub step: u64,
+    pub pc: i64,
+    /// The instruction at pc before it ran
+    pub instruction: Instruction,
+    /// The registers after it ran
+    pub registers: [i64; 4],
+}
+
+pub struct TraceWriter<W: Write> {
+    output: W,
+    registers: [i64; 4],
+}
+
+impl<W: Write> TraceWriter<W> {
+    /// Starts a trace of a machine with these registers.
+    pub fn new(mut output: W, registers: &[i64; 4]) -> io::Result<TraceWriter<W>> {
+        output.write_all(MAGIC)?;
+        output.write_all(&[VERSION])?;
+        for value in registers {
+            write_number(&mut output, *value)?;
+        }
+        Ok(TraceWriter {
+            output,
+            registers: *registers,
+        })
+    }
+
+    /// Adds a step: the pc and instruction it ran and the registers after.
+    pub fn record(
+        &mut self,
+        pc: i64,
+        instruction: &Instruction,
+        registers: &[i64; 4],
+    ) -> io::Result<()> {
+        let changed = (0..4)
+            .filter(|i| registers[*i] != self.registers[*i])
+            .fold(0, |mask, i| mask | 1 << i);
+
+        write_number(&mut self.output, pc)?;
+        self.output
+            .write_all(&[opcode(instruction) << 4 | changed])?;
+        for operand in instruction.operands() {
+            match operand {
+                Operand::Register(reg) => self.output.write_all(&[reg as u8])?,
+                Operand::Value(value) => {
+                    self.output.write_all(&[IMMEDIATE])?;
+                    write_number(&mut self.output, value as i64)?;
+                }
+            }
+        }
+        for i in (0..4).filter(|i| changed & 1 << i != 0) {
+            write_number(
+                &mut self.output,
+                registers[i].wrapping_sub(self.registers[i]),
+            )?;
+        }
+
+        self.registers = *registers;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.output.flush()?;
+        Ok(self.output)
+    }
+}
+
+/// Reads the steps of a trace back, in order.
+pub struct TraceReader<R: Read> {
+    input: R,
+    registers: [i64; 4],
+    step: u64,
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut input: R) -> io::Result<TraceReader<R>> {
+        let mut header = [0; 5];
+        input.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid("Not an assembunny trace"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid(&format!("Unknown trace version {}", header[4])));
+        }
+        let mut registers = [0; 4];
+        for value in registers.iter_mut() {
+            *value = read_number(&mut input)?;
+        }
+        Ok(TraceReader {
+            input,
+            registers,
+            step: 0,
+        })
+    }
+
+    /// The registers the run started with.
+    pub fn initial_registers(&self) -> [i64; 4] {
+        self.registers
+    }
+
+    fn read_step(&mut self, first: u8) -> io::Result<TraceStep> {
+        let pc = read_number_from(&mut self.input, first)?;
+        let header = read_byte(&mut self.input)?;
+        let changed = header & 0xf;
+
+        let operand = |input: &mut R| -> io::Result<Operand> {
+            match read_byte(input)? {
+                IMMEDIATE => {
+                    let value = read_number(input)?;
+                    i32::try_from(value)
+                        .map(Operand::Value)
+                        .map_err(|_| invalid("Immediate out of range"))
+                }
+                index => Register::ALL
+                    .get(index as usize)
+                    .map(|reg| Operand::Register(*reg))
+                    .ok_or_else(|| invalid(&format!("Unknown operand kind {}", index))),
+            }
+        };
+        let instruction = match header >> 4 {
+            0 => Instruction::Nop,
+            1 => Instruction::Add(operand(&mut self.input)?, operand(&mut self.input)?),
+            2 => Instruction::Copy(operand(&mut self.input)?, operand(&mut self.input)?),
+            3 => Instruction::Increment(operand(&mut self.input)?),
+            4 => Instruction::Decrement(operand(&mut self.input)?),
+            5 => Instruction::JumpNotZero(operand(&mut self.input)?, operand(&mut self.input)?),
+            6 => Instruction::Toggle(operand(&mut self.input)?),
+            7 => Instruction::Out(operand(&mut self.input)?),
+            8 => Instruction::Multiply(
+                operand(&mut self.input)?,
+                operand(&mut self.input)?,
+                operand(&mut self.input)?,
+            ),
+            code => return Err(invalid(&format!("Unknown opcode {}", code))),
+        };
+
+        for i in (0..4).filter(|i| changed & 1 << i != 0) {
+            self.registers[i] = self.registers[i].wrapping_add(read_number(&mut self.input)?);
+        }
+
+        self.step += 1;
+        Ok(TraceStep {
+            step: self.step,
+            pc,
+            instruction,
+            registers: self.registers,
+        })
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceStep>;
+
+    fn next(&mut self) -> Option<io::Result<TraceStep>> {
+        // Running out of input between steps is the end of the trace
+        let mut first = [0];
+        match self.input.read(&mut first) {
+            Ok(0) => None,
+            Ok(_) => Some(self.read_step(first[0])),
+            Err(error) => Some(Err(error)),
+        }
+    }
+}
+
+fn opcode(instruction: &Instruction) -> u8 {
+    match instruction {
+        Instruction::Nop => 0,
+        Instruction::Add(..) => 1,
+        Instruction::Copy(..) => 2,
+        Instruction::Increment(_) => 3,
+        Instruction::Decrement(_) => 4,
+        Instruction::JumpNotZero(..) => 5,
+        Instruction::Toggle(_) => 6,
+        Instruction::Out(_) => 7,
+        Instruction::Multiply(..) => 8,
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn write_number<W: Write>(output: &mut W, value: i64) -> io::Result<()> {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    loop {
+        let byte = (zigzag & 0x7f) as u8;
+        zigzag >>= 7;
+        if zigzag == 0 {
+            return output.write_all(&[byte]);
+        }
+        output.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_byte<R: Read>(input: &mut R) -> io::Result<u8> {
+    let mut byte = [0];
+    input.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_number<R: Read>(input: &mut R) -> io::Result<i64> {
+    let first = read_byte(input)?;
+    read_number_from(input, first)
+}
+
+/// Reads a number whose first byte has already been read.
+fn read_number_from<R: Read>(input: &mut R, first: u8) -> io::Result<i64> {
+    let mut zigzag = 0u64;
+    let mut byte = first;
+    for shift in (0..64).step_by(7) {
+        zigzag |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64));
+        }
+        byte = read_byte(input)?;
+    }
+    Err(invalid("Number too long"))
+}
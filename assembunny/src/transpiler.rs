@@ -0,0 +1,268 @@
+use crate::{
+    instruction::{Instruction, Operand},
+    machine::Width,
+};
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{BufRead, BufReader},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// Turns a program into a standalone Rust function:
+///
+/// ```text
+/// pub fn run(
+///     registers: &mut [i64; 4],
+///     out: &mut dyn FnMut(i64, i64, &[i64; 4]) -> bool,
+/// ) -> Result<i64, i64>
+/// ```
+///
+/// The registers become locals and each pc an arm of a `match` inside a
+/// `loop`, with jumps setting the next pc. `out` is given the value, the pc
+/// after the `out` and the registers, and returns false to stop. The function
+/// returns the pc it stopped at, or `Err` with the pc of an instruction whose
+/// result doesn't fit in `width`, leaving the registers as they were before
+/// it like the interpreter does.
+///
+/// Programs using `tgl` rewrite themselves, so they can't be transpiled and
+/// have to run in the interpreter. Give it the program as written rather
+/// than the optimizer's output, whose rewritten loops rely on the
+/// interpreter checking when they're safe to use.
+pub fn transpile(instructions: &[Instruction], width: Width) -> Result<String, String> {
+    if let Some(pc) = instructions
+        .iter()
+        .position(|i| matches!(i, Instruction::Toggle(_)))
+    {
+        return Err(format!("Can't transpile tgl at {}", pc));
+    }
+
+    let mut source = String::new();
+    source += "#[allow(unused_mut, unused_variables, unreachable_code)]\n";
+    source += "pub fn run(\n";
+    source += "    registers: &mut [i64; 4],\n";
+    source += "    out: &mut dyn FnMut(i64, i64, &[i64; 4]) -> bool,\n";
+    source += ") -> Result<i64, i64> {\n";
+    source += match width {
+        Width::I32 => "    let fits = |value: &i64| i32::try_from(*value).is_ok();\n",
+        Width::I64 => "    let fits = |_: &i64| true;\n",
+    };
+    source += "    let [mut a, mut b, mut c, mut d] = *registers;\n";
+    source += "    let mut pc: i64 = 0;\n";
+    source += "    loop {\n";
+    source += "        pc = match pc {\n";
+    for (pc, instruction) in instructions.iter().enumerate() {
+        writeln!(source, "            // {}", instruction).unwrap();
+        writeln!(
+            source,
+            "            {} => {},",
+            pc,
+            arm(pc as i64, instruction)
+        )
+        .unwrap();
+    }
+    source += "            _ => break,\n";
+    source += "        };\n";
+    source += "    }\n";
+    source += "    *registers = [a, b, c, d];\n";
+    source += "    Ok(pc)\n";
+    source += "}\n";
+    Ok(source)
+}
+
+/// The body of a match arm, evaluating to the next pc.
+fn arm(pc: i64, instruction: &Instruction) -> String {
+    let next = pc + 1;
+    if !instruction.is_valid() {
+        return next.to_string();
+    }
+    // Arithmetic is checked, leaving through Err(pc) if it doesn't fit
+    let write = |dst: &Operand, value: String| {
+        format!(
+            "{{ let Some(value) = {}.filter(fits) else {{ *registers = [a, b, c, d]; return Err({}); }}; {} = value; {} }}",
+            value, pc, dst, next
+        )
+    };
+    match instruction {
+        Instruction::Nop => next.to_string(),
+        Instruction::Add(src, dst) => {
+            write(dst, format!("{}.checked_add({})", dst, value(src)))
+        }
+        Instruction::Copy(src, dst) => write(dst, format!("Some({})", value(src))),
+        Instruction::Increment(dst) => write(dst, format!("{}.checked_add(1)", dst)),
+        Instruction::Decrement(dst) => write(dst, format!("{}.checked_sub(1)", dst)),
+        Instruction::Multiply(x, y, dst) => write(
+            dst,
+            format!(
+                "{}.checked_mul({}).and_then(|product| {}.checked_add(product))",
+                value(x),
+                value(y),
+                dst
+            ),
+        ),
+        Instruction::JumpNotZero(test, offset) => {
+            let target = match offset {
+                Operand::Value(offset) => (pc + *offset as i64).to_string(),
+                Operand::Register(reg) => format!("{} + {}", pc, reg),
+            };
+            match test {
+                Operand::Value(0) => next.to_string(),
+                Operand::Value(_) => target,
+                Operand::Register(reg) => {
+                    format!("if {} != 0 {{ {} }} else {{ {} }}", reg, target, next)
+                }
+            }
+        }
+        Instruction::Out(src) => format!(
+            "{{ if !out({}, {}, &[a, b, c, d]) {{ *registers = [a, b, c, d]; return Ok({}); }} {} }}",
+            src, next, next, next
+        ),
+        Instruction::Toggle(_) => unreachable!("Checked in transpile"),
+    }
+}
+
+/// An operand as an `i64` expression that methods can be called on.
+fn value(operand: &Operand) -> String {
+    match operand {
+        Operand::Register(reg) => reg.to_string(),
+        Operand::Value(value) => format!("({}i64)", value),
+    }
+}
+
+/// The `main` around a transpiled `run`. It takes the registers as
+/// arguments and prints `out VALUE PC A B C D` for each output, then
+/// `halt PC A B C D` or `overflow PC A B C D` at the end.
+const MAIN: &str = r#"
+use std::io::Write;
+
+fn main() {
+    let values: Vec<i64> = std::env::args()
+        .skip(1)
+        .map(|arg| arg.parse().expect("Registers must be numbers"))
+        .collect();
+    let mut registers = [values[0], values[1], values[2], values[3]];
+    let mut stdout = std::io::stdout().lock();
+    let result = run(&mut registers, &mut |value, pc, r| {
+        writeln!(stdout, "out {} {} {} {} {} {}", value, pc, r[0], r[1], r[2], r[3]).is_ok()
+    });
+    let [a, b, c, d] = registers;
+    let (end, pc) = match result {
+        Ok(pc) => ("halt", pc),
+        Err(pc) => ("overflow", pc),
+    };
+    let _ = writeln!(stdout, "{} {} {} {} {} {}", end, pc, a, b, c, d);
+}
+"#;
+
+/// How a native run ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NativeExit {
+    /// Left the program at this pc with these registers
+    Halted(i64, [i64; 4]),
+    /// The instruction at this pc overflowed, leaving these registers
+    Overflowed(i64, [i64; 4]),
+    /// The output callback asked to stop
+    Stopped,
+}
+
+/// A transpiled program compiled to an executable with `rustc`.
+#[derive(Clone, Debug)]
+pub struct Native {
+    executable: PathBuf,
+}
+
+impl Native {
+    /// Transpiles and compiles the program in `directory`, with registers
+    /// of this width.
+    pub fn build(
+        instructions: &[Instruction],
+        width: Width,
+        directory: &Path,
+    ) -> Result<Native, String> {
+        let source = transpile(instructions, width)? + MAIN;
+        fs::create_dir_all(directory)
+            .map_err(|error| format!("Failed to create {}: {}", directory.display(), error))?;
+        let source_path = directory.join("program.rs");
+        let executable = directory.join("program");
+        fs::write(&source_path, source)
+            .map_err(|error| format!("Failed to write {}: {}", source_path.display(), error))?;
+
+        let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+        let output = Command::new(&rustc)
+            .args(["--edition", "2021", "-O", "-C", "overflow-checks=on", "-o"])
+            .arg(&executable)
+            .arg(&source_path)
+            .output()
+            .map_err(|error| format!("Failed to run {}: {}", rustc, error))?;
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to compile the transpiled program:\n{}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(Native { executable })
+    }
+
+    /// Runs the program from these registers. `on_out` is called like the
+    /// `out` of [`transpile`], returning `Break` to stop.
+    pub fn run(
+        &self,
+        registers: &[i64; 4],
+        mut on_out: impl FnMut(i64, i64, &[i64; 4]) -> ControlFlow<()>,
+    ) -> Result<NativeExit, String> {
+        let mut child = Command::new(&self.executable)
+            .args(registers.iter().map(|value| value.to_string()))
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|error| format!("Failed to start native program: {}", error))?;
+        let stdout = child.stdout.take().expect("Stdout is piped");
+
+        let mut exit = None;
+        for line in BufReader::new(stdout).lines() {
+            let line = line.map_err(|error| format!("Failed to read output: {}", error))?;
+            let numbers = line
+                .split_whitespace()
+                .skip(1)
+                .map(|number| number.parse::<i64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format!("Unexpected output \"{}\"", line))?;
+            match (line.split_whitespace().next(), &numbers[..]) {
+                (Some("out"), &[value, pc, a, b, c, d]) => {
+                    if on_out(value, pc, &[a, b, c, d]).is_break() {
+                        exit = Some(NativeExit::Stopped);
+                        break;
+                    }
+                }
+                (Some("halt"), &[pc, a, b, c, d]) => {
+                    exit = Some(NativeExit::Halted(pc, [a, b, c, d]))
+                }
+                (Some("overflow"), &[pc, a, b, c, d]) => {
+                    exit = Some(NativeExit::Overflowed(pc, [a, b, c, d]))
+                }
+                _ => return Err(format!("Unexpected output \"{}\"", line)),
+            }
+        }
+
+        if exit == Some(NativeExit::Stopped) {
+            // It may well never end by itself
+            let _ = child.kill();
+        }
+        let status = child
+            .wait()
+            .map_err(|error| format!("Failed to wait for native program: {}", error))?;
+        match exit {
+            Some(NativeExit::Halted(..) | NativeExit::Overflowed(..)) if !status.success() => {
+                Err(format!("Native program failed: {}", status))
+            }
+            Some(exit) => Ok(exit),
+            None => Err(format!("Native program failed: {}", status)),
+        }
+    }
+}
+
+/// A directory to build native programs in, unique to this process.
+pub fn build_directory() -> PathBuf {
+    std::env::temp_dir().join(format!("assembunny-{}", std::process::id()))
+}
@@ -0,0 +1,16 @@
+use assembunny::{parse_program, Discard, Instruction, Machine};
+
+#[test]
+fn fetch_puts_back_a_loop_entered_with_a_bad_counter() {
+    let program = parse_program("cpy 0 b\ninc a\ndec b\njnz b -2\n", "test").unwrap();
+    let mut machine = Machine::new(program.clone());
+    machine.optimize();
+    machine.step(&mut Discard);
+
+    // b is 0, so the rewritten add can't run and the loop is put back
+    assert!(matches!(machine.current(), Some(Instruction::Add(..))));
+    assert_eq!(machine.fetch(), Some(program[1]));
+    assert_eq!(machine.instructions(), &program[..]);
+    machine.step(&mut Discard);
+    assert_eq!(machine.registers(), &[1, 0, 0, 0]);
+}
@@ -0,0 +1,154 @@
+use assembunny::{decompile, parse_program, Discard, Instruction, Machine, Register};
+
+fn program(source: &str) -> Vec<Instruction> {
+    parse_program(source, "test").expect("Failed to parse test program")
+}
+
+fn instruction(source: &str) -> Instruction {
+    program(source)[0]
+}
+
+fn run(source: &str) -> Machine {
+    let mut machine = Machine::new(program(source));
+    machine.run(&mut Discard);
+    machine
+}
+
+#[test]
+fn toggle_table() {
+    let cases = [
+        ("nop", "nop"),
+        ("inc a", "dec a"),
+        ("dec a", "inc a"),
+        ("tgl a", "inc a"),
+        ("tgl 2", "inc 2"),
+        ("out b", "inc b"),
+        ("out 7", "inc 7"),
+        ("jnz a 2", "cpy a 2"),
+        ("jnz 1 c", "cpy 1 c"),
+        ("cpy a b", "jnz a b"),
+        ("cpy 4 b", "jnz 4 b"),
+        ("add a b", "jnz a b"),
+        ("mul a b c", "mul a b c"),
+    ];
+    for (before, after) in cases {
+        assert_eq!(
+            instruction(before).toggled().to_string(),
+            after,
+            "toggling {}",
+            before
+        );
+    }
+}
+
+#[test]
+fn toggling_twice_only_restores_some_instructions() {
+    for source in ["inc a", "jnz a b", "cpy a b", "nop", "mul a b c"] {
+        let instruction = instruction(source);
+        assert_eq!(instruction.toggled().toggled(), instruction, "{}", source);
+    }
+    assert_eq!(
+        instruction("dec a").toggled().toggled().to_string(),
+        "dec a"
+    );
+    assert_eq!(
+        instruction("tgl a").toggled().toggled().to_string(),
+        "dec a"
+    );
+    assert_eq!(
+        instruction("add a b").toggled().toggled().to_string(),
+        "cpy a b"
+    );
+}
+
+#[test]
+fn writing_to_an_immediate_is_invalid() {
+    for source in ["cpy 1 2", "cpy a 2", "add a 2", "mul a b 3"] {
+        assert!(!instruction(source).is_valid(), "{}", source);
+    }
+    for source in ["tgl 2", "out 3"] {
+        assert!(!instruction(source).toggled().is_valid(), "{}", source);
+    }
+    for source in [
+        "cpy 1 a",
+        "add 1 a",
+        "mul 1 2 a",
+        "inc a",
+        "jnz 1 2",
+        "tgl 2",
+        "out 3",
+    ] {
+        assert!(instruction(source).is_valid(), "{}", source);
+    }
+}
+
+#[test]
+fn invalid_instructions_are_skipped() {
+    // jnz 1 2 becomes cpy 1 2, which must not jump or write anything
+    let machine = run("tgl 1\njnz 1 2\ninc a\ninc b");
+    assert_eq!(machine.registers(), &[1, 1, 0, 0]);
+    assert_eq!(machine.instructions()[1].to_string(), "cpy 1 2");
+
+    // tgl turning itself into inc 0, which is skipped on the way round
+    let machine = run("cpy 2 b\ntgl 0\ndec b\njnz b -2\ninc a");
+    assert_eq!(machine.instructions()[1].to_string(), "inc 0");
+    assert_eq!(machine.registers(), &[1, 0, 0, 0]);
+}
+
+#[test]
+fn invalid_instructions_decompile_as_skipped() {
+    let source = decompile(&program("cpy 1 2\nadd 3 4\ninc a"));
+    assert!(source.contains("// skipped: cpy 1 2"), "{}", source);
+    assert!(source.contains("// skipped: add 3 4"), "{}", source);
+    assert!(!source.contains("2 = 1"), "{}", source);
+    assert!(!source.contains("4 += 3"), "{}", source);
+}
+
+#[test]
+fn tgl_takes_any_operand() {
+    let machine = run("tgl 2\ninc a\ncpy 5 a");
+    assert_eq!(machine.instructions()[2].to_string(), "jnz 5 a");
+    assert_eq!(machine.register(Register::A), 1);
+
+    let machine = run("cpy -1 c\ninc a\ntgl c");
+    assert_eq!(machine.instructions()[1].to_string(), "dec a");
+
+    let machine = run("tgl later\nnop\nlater: inc a");
+    assert_eq!(machine.instructions()[2].to_string(), "dec a");
+    assert_eq!(machine.register(Register::A), -1);
+}
+
+#[test]
+fn tgl_of_instructions_outside_the_puzzle() {
+    let machine = run("tgl 1\nnop\ntgl 1\nadd 3 1\ntgl 1\nout a\ntgl 1\nmul 2 3 b");
+    assert_eq!(
+        machine
+            .instructions()
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>(),
+        [
+            "tgl 1",
+            "nop",
+            "tgl 1",
+            "jnz 3 1",
+            "tgl 1",
+            "inc a",
+            "tgl 1",
+            "mul 2 3 b"
+        ]
+    );
+}
+
+#[test]
+fn tgl_outside_the_program_does_nothing() {
+    let machine = run("tgl 10\ntgl -10\ninc a");
+    assert_eq!(machine.instructions()[..2], program("tgl 10\ntgl -10")[..]);
+    assert_eq!(machine.register(Register::A), 1);
+}
+
+#[test]
+fn puzzle_example() {
+    let machine = run("cpy 2 a\ntgl a\ntgl a\ntgl a\ncpy 1 a\ndec a\ndec a");
+    assert_eq!(machine.register(Register::A), 3);
+}
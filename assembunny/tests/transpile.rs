@@ -0,0 +1,125 @@
+use assembunny::{parse_program, transpile, Machine, Native, NativeExit, Status, Width};
+use std::ops::ControlFlow;
+
+/// Runs a program both ways from the same registers and checks the outputs
+/// and final state agree. At most `limit` values are output. The native
+/// version is always built from the program as written.
+fn check(name: &str, source: &str, registers: [i64; 4], optimize: bool, limit: usize) {
+    check_width(name, source, registers, optimize, limit, Width::I64);
+}
+
+fn check_width(
+    name: &str,
+    source: &str,
+    registers: [i64; 4],
+    optimize: bool,
+    limit: usize,
+    width: Width,
+) {
+    let instructions = parse_program(source, name).expect("Failed to parse test program");
+
+    let mut machine = Machine::new(instructions.clone());
+    machine.set_width(width);
+    for (index, value) in registers.iter().enumerate() {
+        machine.set_register(assembunny::Register::ALL[index], *value);
+    }
+    if optimize {
+        machine.optimize();
+    }
+    let mut interpreted = Vec::new();
+    let status = machine.run(&mut |value| {
+        interpreted.push(value);
+        if interpreted.len() == limit {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
+
+    let directory =
+        std::env::temp_dir().join(format!("assembunny-test-{}-{}", name, std::process::id()));
+    let native = Native::build(&instructions, width, &directory).expect("Failed to build");
+    let mut compiled = Vec::new();
+    let exit = native
+        .run(&registers, |value, _, _| {
+            compiled.push(value);
+            if compiled.len() == limit {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .expect("Failed to run");
+    std::fs::remove_dir_all(&directory).ok();
+
+    assert_eq!(compiled, interpreted, "{}: outputs", name);
+    match (status, exit) {
+        (Status::Halted, NativeExit::Halted(pc, registers)) => {
+            assert_eq!(pc, machine.pc(), "{}: final pc", name);
+            assert_eq!(&registers, machine.registers(), "{}: registers", name);
+        }
+        (Status::Overflowed, NativeExit::Overflowed(pc, registers)) => {
+            assert_eq!(pc, machine.pc(), "{}: overflow pc", name);
+            assert_eq!(&registers, machine.registers(), "{}: registers", name);
+        }
+        (Status::Stopped, NativeExit::Stopped) => (),
+        _ => panic!("{}: interpreter {:?}, native {:?}", name, status, exit),
+    }
+}
+
+#[test]
+fn fibonacci() {
+    let source = "cpy 1 a\ncpy 1 b\ncpy 26 d\njnz c 2\njnz 1 5\ncpy 7 c\ninc d\ndec c\n\
+                  jnz c -2\ncpy a c\ninc a\ndec b\njnz b -2\ncpy c b\ndec d\njnz d -6\n\
+                  cpy 13 c\ncpy 14 d\ninc a\ndec d\njnz d -2\ndec c\njnz c -5";
+    check("fibonacci", source, [0; 4], false, usize::MAX);
+    check("fibonacci_c", source, [0, 0, 1, 0], false, usize::MAX);
+    check(
+        "fibonacci_optimized",
+        source,
+        [0, 0, 1, 0],
+        true,
+        usize::MAX,
+    );
+}
+
+#[test]
+fn clock_signal() {
+    let source = "cpy a d\ncpy 4 c\ncpy 633 b\ninc d\ndec b\njnz b -2\ndec c\njnz c -5\n\
+                  cpy d a\njnz 0 0\ncpy a b\ncpy 0 a\ncpy 2 c\njnz b 2\njnz 1 6\ndec b\n\
+                  dec c\njnz c -4\ninc a\njnz 1 -7\ncpy 2 b\njnz c 2\njnz 1 4\ndec b\n\
+                  dec c\njnz 1 -4\njnz 0 0\nout b\njnz a -19\njnz 1 -21";
+    check("clock", source, [198, 0, 0, 0], false, 100);
+    check("clock_optimized", source, [5, 0, 0, 0], true, 100);
+}
+
+#[test]
+fn edge_cases() {
+    // Register offsets, negative values, immediate outputs and an invalid
+    // instruction that has to be skipped
+    let source = "cpy -3 a\ncpy 2 b\nout 9\ncpy 1 2\njnz 1 b\ninc c\nadd a c\n\
+                  mul a a d\nout d\njnz 1 b\ndec c";
+    check("edge_cases", source, [0; 4], false, usize::MAX);
+}
+
+#[test]
+fn overflow() {
+    // Doubling until it doesn't fit, which takes longer with i64 registers
+    let source = "cpy 1 a\nout a\ncpy a b\nadd b a\njnz 1 -3";
+    for width in [Width::I32, Width::I64] {
+        check_width("overflow", source, [0; 4], false, usize::MAX, width);
+    }
+    let source = "cpy 100000 a\nmul a a b\nmul b b c\ndec d\njnz 1 -2";
+    for width in [Width::I32, Width::I64] {
+        check_width("overflow_mul", source, [0; 4], false, usize::MAX, width);
+    }
+}
+
+#[test]
+fn tgl_is_refused() {
+    let instructions = parse_program("cpy 2 a\ntgl a\ninc b", "tgl").unwrap();
+    assert_eq!(
+        transpile(&instructions, Width::I64),
+        Err("Can't transpile tgl at 1".to_string())
+    );
+}
@@ -1,8 +1,10 @@
 use clap::Parser;
 use itertools::Itertools;
 use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
     fs::File,
-    io::{BufRead, BufReader},
+    io::{BufRead, BufReader, BufWriter, Write},
 };
 
 #[derive(Parser, Debug)]
@@ -12,14 +14,60 @@ struct Args {
     data_file: String,
     #[arg(long)]
     debug: bool,
+    /// Write every chip movement to this file as JSON lines
+    #[arg(long)]
+    trace: Option<String>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum Destination {
     Bot(u32),
     Output(u32),
 }
 
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Destination::Bot(bot) => write!(f, "bot {}", bot),
+            Destination::Output(bin) => write!(f, "output {}", bin),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Source {
+    Input,
+    Bot(u32),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Input => write!(f, "input"),
+            Source::Bot(bot) => write!(f, "bot {}", bot),
+        }
+    }
+}
+
+/// A single chip moving from `source` to `destination`. Initial `value` lines
+/// happen at step 0 and each bot comparison after that is one step.
+#[derive(Clone, Debug)]
+struct ChipEvent {
+    step: u32,
+    value: u32,
+    source: Source,
+    destination: Destination,
+}
+
+impl ChipEvent {
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"step\":{},\"value\":{},\"source\":\"{}\",\"destination\":\"{}\"}}",
+            self.step, self.value, self.source, self.destination
+        )
+    }
+}
+
 #[derive(Clone, Debug)]
 struct Bot {
     number: u32,
@@ -28,6 +76,151 @@ struct Bot {
     low_value_dest: Destination,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+enum SimulationError {
+    InvalidInstruction(String),
+    DuplicateBot(u32),
+    UndefinedBot(u32),
+    OverfullBot { bot: u32, chips: [u32; 3] },
+    Deadlock(Vec<(u32, u32)>),
+    EmptyOutput(u32),
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimulationError::InvalidInstruction(line) => {
+                write!(f, "Invalid instruction: {}", line)
+            }
+            SimulationError::DuplicateBot(bot) => write!(f, "Bot {} is defined twice", bot),
+            SimulationError::UndefinedBot(bot) => write!(f, "Bot {} is never defined", bot),
+            SimulationError::OverfullBot { bot, chips } => write!(
+                f,
+                "Bot {} was given a third chip: {}, {}, {}",
+                bot, chips[0], chips[1], chips[2]
+            ),
+            SimulationError::Deadlock(stuck) => write!(
+                f,
+                "Deadlock, chips left stuck: {}",
+                stuck
+                    .iter()
+                    .map(|(bot, chip)| format!("bot {} holds {}", bot, chip))
+                    .join(", ")
+            ),
+            SimulationError::EmptyOutput(output) => {
+                write!(f, "Output {} never received a chip", output)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SimulationError {}
+
+#[derive(Debug, Default)]
+struct Simulation {
+    bots: HashMap<u32, Bot>,
+    outputs: HashMap<u32, Vec<u32>>,
+    // Bots holding two chips, in the order they became ready
+    ready: VecDeque<u32>,
+    step: u32,
+    events: Vec<ChipEvent>,
+}
+
+impl Simulation {
+    /// Sets up the bots and hands out the initial chips. On a fault the
+    /// simulation keeps the events up to it, so they can still be traced.
+    fn load(&mut self, lines: &[String]) -> Result<(), SimulationError> {
+        for line in lines.iter().filter(|line| line.starts_with("bot")) {
+            let bot = to_bot(line)?;
+            if self.bots.insert(bot.number, bot.clone()).is_some() {
+                return Err(SimulationError::DuplicateBot(bot.number));
+            }
+        }
+
+        for line in lines.iter().filter(|line| !line.starts_with("bot")) {
+            let (value, dest) = to_value(line)?;
+            self.deliver(value, Source::Input, dest)?;
+        }
+
+        Ok(())
+    }
+
+    fn deliver(
+        &mut self,
+        value: u32,
+        source: Source,
+        dest: Destination,
+    ) -> Result<(), SimulationError> {
+        self.events.push(ChipEvent {
+            step: self.step,
+            value,
+            source,
+            destination: dest,
+        });
+
+        match dest {
+            Destination::Bot(number) => {
+                let bot = self
+                    .bots
+                    .get_mut(&number)
+                    .ok_or(SimulationError::UndefinedBot(number))?;
+                match bot.values {
+                    (None, _) => bot.values.0 = Some(value),
+                    (Some(_), None) => {
+                        bot.values.1 = Some(value);
+                        self.ready.push_back(number);
+                    }
+                    (Some(first), Some(second)) => {
+                        return Err(SimulationError::OverfullBot {
+                            bot: number,
+                            chips: [first, second, value],
+                        })
+                    }
+                }
+            }
+            Destination::Output(bin) => self.outputs.entry(bin).or_default().push(value),
+        }
+
+        Ok(())
+    }
+
+    /// Runs bots until none hold two chips, calling `on_compare` with each
+    /// bot's number and its (low, high) chips as it hands them on.
+    fn run<F>(&mut self, mut on_compare: F) -> Result<(), SimulationError>
+    where
+        F: FnMut(u32, u32, u32),
+    {
+        while let Some(number) = self.ready.pop_front() {
+            self.step += 1;
+            let bot = self.bots.get_mut(&number).unwrap();
+            let (Some(first), Some(second)) = bot.values else {
+                unreachable!("Only bots with two chips are queued");
+            };
+            bot.values = (None, None);
+            let (low_dest, high_dest) = (bot.low_value_dest, bot.high_value_dest);
+
+            let low = first.min(second);
+            let high = first.max(second);
+            on_compare(number, low, high);
+
+            self.deliver(low, Source::Bot(number), low_dest)?;
+            self.deliver(high, Source::Bot(number), high_dest)?;
+        }
+
+        let stuck = self
+            .bots
+            .values()
+            .filter_map(|bot| bot.values.0.map(|chip| (bot.number, chip)))
+            .sorted()
+            .collect_vec();
+        if !stuck.is_empty() {
+            return Err(SimulationError::Deadlock(stuck));
+        }
+
+        Ok(())
+    }
+}
+
 fn main() {
     let args = Args::parse();
 
@@ -36,126 +229,90 @@ fn main() {
     let lines: Vec<String> = reader
         .lines()
         .map(|line| line.expect("Failed to read line"))
+        .filter(|line| !line.trim().is_empty())
         .collect();
 
-    let mut bots: Vec<Bot> = lines
-        .iter()
-        .filter(|line| line.starts_with("bot"))
-        .map(to_bot)
-        .collect();
-    bots.sort_by_key(|b| b.number);
-
-    lines
-        .iter()
-        .filter(|line| line.starts_with("value"))
-        .for_each(|line| {
-            let components = line.split_whitespace().collect_vec();
-            let bot = bots
-                .iter_mut()
-                .find(|b| b.number == components[5].parse().unwrap())
-                .unwrap();
-            if bot.values.0.is_some() {
-                if bot.values.1.is_some() {
-                    panic!("Bot has too many values");
-                }
-
-                bot.values.1 = Some(components[1].parse().unwrap());
-            } else {
-                bot.values.0 = Some(components[1].parse().unwrap());
+    let mut simulation = Simulation::default();
+    let result = simulation.load(&lines).and_then(|_| {
+        simulation.run(|bot, low, high| {
+            if args.debug {
+                println!("Processing: bot {} compares {} and {}", bot, low, high);
             }
-        });
 
-    let mut part2: u64 = 1;
-    let mut part2_count = 0;
-    'processing: loop {
-        let mut i = 0;
-        while i < bots.len() {
-            let bot = bots.get(i).unwrap().clone();
-            if bot.values.0.is_some() && bot.values.1.is_some() {
-                if args.debug {
-                    println!("Processing: {:?}", bot);
-                }
-                let low = bot.values.0.unwrap().min(bot.values.1.unwrap());
-                let high = bot.values.0.unwrap().max(bot.values.1.unwrap());
-
-                if low == 17 && high == 61 {
-                    println!("Part 1: {}", bot.number);
-                    if part2_count == 3 {
-                        break 'processing;
-                    }
-                }
-
-                match bot.low_value_dest {
-                    Destination::Bot(dest) => {
-                        let lb = bots.iter_mut().find(|b| b.number == dest).unwrap();
-                        if lb.values.0.is_none() {
-                            lb.values.0 = Some(low);
-                        } else if lb.values.1.is_none() {
-                            lb.values.1 = Some(low);
-                        } else {
-                            panic!("Too many values");
-                        }
-                    }
-                    Destination::Output(bin) => {
-                        if bin < 3 {
-                            part2 *= low as u64;
-                            part2_count += 1;
-
-                            if part2_count == 3 {
-                                break 'processing;
-                            }
-                        }
-                    }
-                }
+            if low == 17 && high == 61 {
+                println!("Part 1: {}", bot);
+            }
+        })
+    });
 
-                match bot.high_value_dest {
-                    Destination::Bot(dest) => {
-                        let hb = bots.iter_mut().find(|b| b.number == dest).unwrap();
-                        if hb.values.0.is_none() {
-                            hb.values.0 = Some(high);
-                        } else if hb.values.1.is_none() {
-                            hb.values.1 = Some(high);
-                        } else {
-                            panic!("Too many values");
-                        }
-                    }
-                    Destination::Output(bin) => {
-                        if bin < 3 {
-                            part2 *= high as u64;
-                            part2_count += 1;
-
-                            if part2_count == 3 {
-                                break 'processing;
-                            }
-                        }
-                    }
-                }
+    // Write the trace before reporting any fault so the run can be inspected
+    if let Some(trace) = &args.trace {
+        let file = File::create(trace).expect("Failed to create trace file");
+        let mut writer = BufWriter::new(file);
+        for event in simulation.events.iter() {
+            writeln!(writer, "{}", event.to_json()).expect("Failed to write trace");
+        }
+    }
 
-                bots.get_mut(i).unwrap().values = (None, None);
-            }
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
 
-            i += 1;
+    let part2 = (0..3)
+        .map(|bin| {
+            simulation
+                .outputs
+                .get(&bin)
+                .and_then(|chips| chips.first())
+                .map(|chip| *chip as u64)
+                .ok_or(SimulationError::EmptyOutput(bin))
+        })
+        .product::<Result<u64, _>>();
+    match part2 {
+        Ok(part2) => println!("Part 2: {}", part2),
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
         }
     }
+}
 
-    println!("Part 2: {}", part2);
+fn to_destination(kind: &str, number: &str, line: &str) -> Result<Destination, SimulationError> {
+    let number = number
+        .parse()
+        .map_err(|_| SimulationError::InvalidInstruction(line.to_string()))?;
+    match kind {
+        "bot" => Ok(Destination::Bot(number)),
+        "output" => Ok(Destination::Output(number)),
+        _ => Err(SimulationError::InvalidInstruction(line.to_string())),
+    }
 }
 
-fn to_bot(line: &String) -> Bot {
+fn to_bot(line: &str) -> Result<Bot, SimulationError> {
     let components = line.split_whitespace().collect_vec();
+    if components.len() != 12 {
+        return Err(SimulationError::InvalidInstruction(line.to_string()));
+    }
 
-    Bot {
-        number: components[1].parse().unwrap(),
+    Ok(Bot {
+        number: components[1]
+            .parse()
+            .map_err(|_| SimulationError::InvalidInstruction(line.to_string()))?,
         values: (None, None),
-        low_value_dest: match components[5] {
-            "bot" => Destination::Bot(components[6].parse().unwrap()),
-            "output" => Destination::Output(components[6].parse().unwrap()),
-            _ => panic!("Unexpected destination"),
-        },
-        high_value_dest: match components[10] {
-            "bot" => Destination::Bot(components[11].parse().unwrap()),
-            "output" => Destination::Output(components[11].parse().unwrap()),
-            _ => panic!("Unexpected destination"),
-        },
+        low_value_dest: to_destination(components[5], components[6], line)?,
+        high_value_dest: to_destination(components[10], components[11], line)?,
+    })
+}
+
+fn to_value(line: &str) -> Result<(u32, Destination), SimulationError> {
+    let components = line.split_whitespace().collect_vec();
+    if components.len() != 6 || components[0] != "value" {
+        return Err(SimulationError::InvalidInstruction(line.to_string()));
     }
+
+    let value = components[1]
+        .parse()
+        .map_err(|_| SimulationError::InvalidInstruction(line.to_string()))?;
+    Ok((value, to_destination(components[4], components[5], line)?))
 }
@@ -0,0 +1,220 @@
+use crate::Safety;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, path::Path};
+
+const ORDINALS: [&str; 10] = [
+    "first", "second", "third", "fourth", "fifth", "sixth", "seventh", "eighth", "ninth", "tenth",
+];
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Floor {
+    #[serde(default)]
+    pub microchips: Vec<String>,
+    #[serde(default)]
+    pub generators: Vec<String>,
+}
+
+/// The structured file format, e.g. in TOML:
+///
+/// ```toml
+/// [[floors]]
+/// microchips = ["hydrogen", "lithium"]
+///
+/// [[floors]]
+/// generators = ["hydrogen"]
+/// ```
+#[derive(Serialize, Deserialize)]
+struct Layout {
+    floors: Vec<Floor>,
+}
+
+impl Floor {
+    pub fn items(&self) -> Vec<Item> {
+        self.generators
+            .iter()
+            .map(|element| Item::Generator(element.clone()))
+            .chain(
+                self.microchips
+                    .iter()
+                    .map(|element| Item::Microchip(element.clone())),
+            )
+            .collect_vec()
+    }
+
+    pub fn remove(&mut self, item: &Item) -> bool {
+        let (items, element) = match item {
+            Item::Generator(element) => (&mut self.generators, element),
+            Item::Microchip(element) => (&mut self.microchips, element),
+        };
+        match items.iter().position(|e| e == element) {
+            Some(index) => {
+                items.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn add(&mut self, item: &Item) {
+        match item {
+            Item::Generator(element) => self.generators.push(element.clone()),
+            Item::Microchip(element) => self.microchips.push(element.clone()),
+        }
+    }
+
+    pub fn is_safe(&self, safety: Safety) -> bool {
+        self.microchips.iter().all(|m| {
+            let other_generator_here = self.generators.iter().any(|g| g != m);
+            !safety.is_fried(self.generators.contains(m), other_generator_here)
+        })
+    }
+
+    /// Parses a line such as "The second floor contains a hydrogen generator
+    /// and a lithium-compatible microchip."
+    pub fn from_puzzle_text(line: &str) -> Result<Floor, String> {
+        let contents = line
+            .trim()
+            .trim_end_matches('.')
+            .split_once(" floor contains ")
+            .map(|(_, contents)| contents)
+            .ok_or("Expected \"The <nth> floor contains ...\"")?;
+
+        let mut floor = Floor::default();
+        if contents == "nothing relevant" {
+            return Ok(floor);
+        }
+
+        for entry in contents
+            .split(", ")
+            .flat_map(|entry| entry.split(" and "))
+            .map(|entry| entry.trim_start_matches("and ").trim())
+            .filter(|entry| !entry.is_empty())
+        {
+            let words = entry.split_whitespace().collect_vec();
+            match words[..] {
+                ["a" | "an", element, "generator"] => floor.generators.push(element.to_string()),
+                ["a" | "an", chip, "microchip"] => match chip.strip_suffix("-compatible") {
+                    Some(element) => floor.microchips.push(element.to_string()),
+                    None => return Err(format!("Unexpected microchip \"{}\"", entry)),
+                },
+                _ => return Err(format!("Unexpected item \"{}\"", entry)),
+            }
+        }
+
+        Ok(floor)
+    }
+
+    /// Writes the floor back out the way the puzzle input describes it.
+    pub fn to_puzzle_text(&self, index: usize) -> String {
+        let ordinal = ORDINALS
+            .get(index)
+            .map(|o| o.to_string())
+            .unwrap_or_else(|| format!("{}th", index + 1));
+
+        let items =
+            self.generators
+                .iter()
+                .map(|element| format!("{} {} generator", article(element), element))
+                .chain(self.microchips.iter().map(|element| {
+                    format!("{} {}-compatible microchip", article(element), element)
+                }))
+                .collect_vec();
+
+        let contents = match items.len() {
+            0 => "nothing relevant".to_string(),
+            1 => items[0].clone(),
+            2 => format!("{} and {}", items[0], items[1]),
+            n => format!("{}, and {}", items[..n - 1].join(", "), items[n - 1]),
+        };
+
+        format!("The {} floor contains {}.", ordinal, contents)
+    }
+}
+
+fn article(word: &str) -> &'static str {
+    if word.starts_with(['a', 'e', 'i', 'o', 'u']) {
+        "an"
+    } else {
+        "a"
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Item {
+    Generator(String),
+    Microchip(String),
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Item::Generator(element) => write!(f, "{} generator", element),
+            Item::Microchip(element) => write!(f, "{} microchip", element),
+        }
+    }
+}
+
+/// Loads floors from puzzle text, or from TOML or JSON based on the extension.
+pub fn load(path: &str) -> Result<Vec<Floor>, String> {
+    let contents =
+        fs::read_to_string(path).map_err(|error| format!("Failed to read {}: {}", path, error))?;
+
+    let floors = match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("toml") => {
+            toml::from_str::<Layout>(&contents)
+                .map_err(|error| format!("{}: {}", path, error))?
+                .floors
+        }
+        Some("json") => {
+            serde_json::from_str::<Layout>(&contents)
+                .map_err(|error| format!("{}: {}", path, error))?
+                .floors
+        }
+        _ => contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                Floor::from_puzzle_text(line)
+                    .map_err(|error| format!("{}:{}: {}", path, number + 1, error))
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+
+    if floors.is_empty() {
+        return Err(format!("{}: No floors", path));
+    }
+
+    Ok(floors)
+}
+
+/// Adds a generator and its microchip from an `<element>@<floor>` argument,
+/// where floors are numbered from 1.
+pub fn add_pair(floors: &mut [Floor], pair: &str) -> Result<(), String> {
+    let (element, floor) = pair
+        .split_once('@')
+        .ok_or_else(|| format!("Expected <element>@<floor>, got \"{}\"", pair))?;
+    let floor: usize = floor
+        .parse()
+        .map_err(|_| format!("Invalid floor in \"{}\"", pair))?;
+    if floor == 0 || floor > floors.len() {
+        return Err(format!(
+            "Floor {} doesn't exist, there are {} floors",
+            floor,
+            floors.len()
+        ));
+    }
+    if floors.iter().any(|f| {
+        f.generators
+            .iter()
+            .chain(f.microchips.iter())
+            .any(|e| e == element)
+    }) {
+        return Err(format!("{} is already in the layout", element));
+    }
+
+    floors[floor - 1].generators.push(element.to_string());
+    floors[floor - 1].microchips.push(element.to_string());
+    Ok(())
+}
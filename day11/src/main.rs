@@ -1,393 +1,535 @@
-use clap::Parser;
+mod layout;
+
+use clap::{Parser, ValueEnum};
 use itertools::Itertools;
+use layout::{Floor, Item};
 use std::{
     cmp::Reverse,
-    collections::{BinaryHeap, HashSet},
-    fs::File,
-    io::{BufRead, BufReader},
+    collections::{BinaryHeap, HashMap},
+    fmt,
 };
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Puzzle input, or a .toml or .json layout
     #[arg(long)]
     data_file: String,
+    /// Add a generator and its microchip, e.g. elerium@1
+    #[arg(long, value_name = "ELEMENT@FLOOR")]
+    add_pair: Vec<String>,
+    /// Print the layout as puzzle text before searching
+    #[arg(long)]
+    print_layout: bool,
     #[arg(long)]
     debug: bool,
+    /// Print every elevator move and the floors after it
+    #[arg(long)]
+    show_moves: bool,
+    /// Replay the moves and check every intermediate layout is safe
+    #[arg(long)]
+    verify: bool,
+    /// Most items the elevator can carry at once
+    #[arg(long, default_value_t = 2)]
+    capacity: usize,
+    /// Pad the layout with empty floors up to this many
+    #[arg(long)]
+    floors: Option<usize>,
+    /// Floor everything has to end up on, defaults to the top floor
+    #[arg(long)]
+    target_floor: Option<usize>,
+    #[arg(long, value_enum, default_value_t = Safety::Standard)]
+    safety: Safety,
 }
 
-#[derive(Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
-struct Floor {
-    microchips: Vec<String>,
-    generators: Vec<String>,
+/// When a microchip gets fried by the generators on its floor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Safety {
+    /// Fried by another generator unless its own generator is there
+    Standard,
+    /// Never fried
+    Off,
 }
 
-#[derive(Clone, Eq)]
-struct State {
-    steps: u32,
-    floors: Vec<Floor>,
-    elevator_floor: usize,
+impl Safety {
+    fn is_fried(&self, own_generator_here: bool, other_generator_here: bool) -> bool {
+        match self {
+            Safety::Standard => other_generator_here && !own_generator_here,
+            Safety::Off => false,
+        }
+    }
 }
 
-impl State {
-    fn steps_to_finish(&self) -> u32 {
-        let mut steps = self.steps;
-        let mut count = 0;
-        for f in 0..(self.floors.len() - 1) {
-            let floor_count =
-                count + self.floors[f].generators.len() + self.floors[f].microchips.len();
-            if floor_count > 0 {
-                // All items will take two trips except the last 2 which will take 1
-                steps += (2 * (floor_count as i32 - 2).max(0)) as u32 + 1;
-            }
+#[derive(Clone, Copy, Debug)]
+struct Rules {
+    capacity: usize,
+    floor_count: usize,
+    target_floor: usize,
+    safety: Safety,
+}
 
-            count = floor_count;
-        }
+impl Rules {
+    /// A lower bound on the elevator trips needed to get `count` items across
+    /// one floor boundary. Each trip after the first has to bring at least one
+    /// item back, so it only gains `capacity - 1` items.
+    fn crossings(&self, count: u32) -> u32 {
+        let capacity = self.capacity as u32;
+        let trips = if count <= capacity {
+            1
+        } else if capacity == 1 {
+            count
+        } else {
+            1 + (count - capacity).div_ceil(capacity - 1)
+        };
 
-        steps
+        2 * trips - 1
     }
 }
 
-impl std::hash::Hash for State {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.floors.hash(state);
-        self.elevator_floor.hash(state);
-    }
+#[derive(Clone, Debug)]
+struct Move {
+    from: usize,
+    to: usize,
+    items: Vec<Item>,
 }
 
-impl PartialEq for State {
-    fn eq(&self, other: &Self) -> bool {
-        self.floors == other.floors && self.elevator_floor == other.elevator_floor
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} to floor {} with {}",
+            if self.to > self.from { "Up" } else { "Down" },
+            self.to + 1,
+            self.items.iter().join(" and ")
+        )
     }
 }
 
-impl PartialOrd for State {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        // We're returning the minimum steps to get to a final state
-        match self.steps_to_finish().partial_cmp(&other.steps_to_finish()) {
-            Some(core::cmp::Ordering::Equal) => {}
-            ord => return ord,
+impl Move {
+    /// Carries the items from the elevator's floor to the next one. Fails if the
+    /// move isn't one the elevator could make or it leaves a chip unprotected.
+    fn apply(&self, floors: &mut [Floor], rules: &Rules) -> Result<(), String> {
+        if self.from.abs_diff(self.to) != 1 || self.to >= floors.len() {
+            return Err(format!(
+                "Can't move from floor {} to floor {}",
+                self.from + 1,
+                self.to + 1
+            ));
         }
-        match self.steps.partial_cmp(&other.steps) {
-            Some(core::cmp::Ordering::Equal) => {}
-            ord => return ord,
+        if self.items.is_empty() || self.items.len() > rules.capacity {
+            return Err(format!("Can't carry {} items", self.items.len()));
         }
-        match self.floors.partial_cmp(&other.floors) {
-            Some(core::cmp::Ordering::Equal) => {}
-            ord => return ord,
+
+        for item in self.items.iter() {
+            if !floors[self.from].remove(item) {
+                return Err(format!("No {} on floor {}", item, self.from + 1));
+            }
+            floors[self.to].add(item);
+        }
+
+        for floor in [self.from, self.to] {
+            if !floors[floor].is_safe(rules.safety) {
+                return Err(format!("A microchip is fried on floor {}", floor + 1));
+            }
         }
-        self.elevator_floor.partial_cmp(&other.elevator_floor)
+
+        Ok(())
     }
-}
 
-impl Ord for State {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+    /// Carries the items without checking the move is allowed, which is what
+    /// `apply` is for.
+    fn carry(&self, floors: &mut [Floor]) {
+        for item in self.items.iter() {
+            floors[self.from].remove(item);
+            floors[self.to].add(item);
+        }
     }
 }
 
-fn main() {
-    let args = Args::parse();
+const FLOOR_BITS: u32 = 4;
+const PAIR_BITS: u32 = 2 * FLOOR_BITS;
+const HEADER_BITS: u32 = 8;
+const MAX_PAIRS: usize = ((u64::BITS - HEADER_BITS) / PAIR_BITS) as usize;
 
-    let file = File::open(&args.data_file).expect("Failed to open file");
-    let reader = BufReader::new(file);
-    let lines: Vec<String> = reader
-        .lines()
-        .map(|line| line.expect("Failed to read line"))
-        .collect();
+/// The search state packed into an integer. Element names don't matter to the
+/// puzzle, only which floor each generator and its chip are on, so the state is
+/// the sorted list of (generator floor, chip floor) pairs and the elevator floor.
+///
+/// Layout from the low bits: elevator floor (4 bits), pair count (4 bits), then
+/// one byte per pair holding the generator floor and the chip floor.
+#[derive(Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash, Debug)]
+struct State(u64);
 
-    let floors = lines.iter().map(to_floor).collect_vec();
+impl State {
+    fn new(elevator_floor: usize, pairs: &mut [(u8, u8)]) -> State {
+        assert!(
+            pairs.len() <= MAX_PAIRS,
+            "At most {} pairs are supported",
+            MAX_PAIRS
+        );
+        pairs.sort_unstable();
+
+        let mut packed = elevator_floor as u64 | (pairs.len() as u64) << FLOOR_BITS;
+        for (i, (generator, microchip)) in pairs.iter().enumerate() {
+            let pair = (*generator as u64) << FLOOR_BITS | *microchip as u64;
+            packed |= pair << (HEADER_BITS + i as u32 * PAIR_BITS);
+        }
 
-    let progress_bar = indicatif::ProgressBar::new_spinner();
+        State(packed)
+    }
 
-    let mut states: BinaryHeap<Reverse<State>> = BinaryHeap::new();
-    states.push(Reverse(State {
-        steps: 0,
-        floors: floors.clone(),
-        elevator_floor: 0,
-    }));
-    let mut seen_states = HashSet::new();
+    fn from_floors(floors: &[Floor], elevator_floor: usize) -> Result<State, String> {
+        if floors.len() > 1 << FLOOR_BITS {
+            return Err(format!("At most {} floors are supported", 1 << FLOOR_BITS));
+        }
 
-    'search: while !states.is_empty() {
-        let state = states.pop().unwrap().0;
+        let microchip_floor = |name: &String| {
+            floors
+                .iter()
+                .position(|f| f.microchips.contains(name))
+                .ok_or_else(|| format!("The {} generator has no microchip", name))
+        };
+
+        let mut pairs = Vec::new();
+        for (floor, f) in floors.iter().enumerate() {
+            for name in f.generators.iter() {
+                pairs.push((floor as u8, microchip_floor(name)? as u8));
+            }
+        }
 
-        if !seen_states.insert(state.clone()) {
-            continue;
+        let microchip_count: usize = floors.iter().map(|f| f.microchips.len()).sum();
+        if pairs.len() != microchip_count {
+            return Err("Every microchip needs a matching generator".to_string());
+        }
+        if pairs.len() > MAX_PAIRS {
+            return Err(format!("At most {} pairs are supported", MAX_PAIRS));
         }
 
-        if args.debug {
-            println!("Step: {}, States: {}", state.steps, states.len());
-        } else {
-            progress_bar.set_message(format!("Step: {}, States: {}", state.steps, states.len()));
+        Ok(State::new(elevator_floor, &mut pairs))
+    }
+
+    fn elevator_floor(&self) -> usize {
+        (self.0 & ((1 << FLOOR_BITS) - 1)) as usize
+    }
+
+    fn pairs(&self) -> Vec<(u8, u8)> {
+        let count = (self.0 >> FLOOR_BITS) & ((1 << FLOOR_BITS) - 1);
+        (0..count as u32)
+            .map(|i| {
+                let pair = self.0 >> (HEADER_BITS + i * PAIR_BITS);
+                (
+                    ((pair >> FLOOR_BITS) & ((1 << FLOOR_BITS) - 1)) as u8,
+                    (pair & ((1 << FLOOR_BITS) - 1)) as u8,
+                )
+            })
+            .collect_vec()
+    }
+
+    fn is_valid(&self, safety: Safety) -> bool {
+        let pairs = self.pairs();
+        pairs.iter().enumerate().all(|(i, (generator, microchip))| {
+            let other_generator_here = pairs
+                .iter()
+                .enumerate()
+                .any(|(j, (g, _))| j != i && g == microchip);
+            !safety.is_fried(generator == microchip, other_generator_here)
+        })
+    }
+
+    fn is_finished(&self, target_floor: usize) -> bool {
+        self.pairs().iter().all(|(generator, microchip)| {
+            *generator as usize == target_floor && *microchip as usize == target_floor
+        })
+    }
+
+    /// Sums a lower bound for every floor boundary between the items and the
+    /// target floor, since each step crosses exactly one boundary.
+    fn steps_to_finish(&self, rules: &Rules) -> u32 {
+        let mut items_on_floor = vec![0u32; rules.floor_count];
+        for (generator, microchip) in self.pairs() {
+            items_on_floor[generator as usize] += 1;
+            items_on_floor[microchip as usize] += 1;
         }
 
-        // Is the current state valid
-        for floor in state.floors.iter() {
-            if floor.generators.len() > 0
-                && floor
-                    .microchips
-                    .iter()
-                    .any(|m| !floor.generators.contains(m))
-            {
-                // This is an invalid state
-                continue 'search;
+        let mut steps = 0;
+        let mut count = 0;
+        for floor_items in items_on_floor.iter().take(rules.target_floor) {
+            count += floor_items;
+            if count > 0 {
+                steps += rules.crossings(count);
             }
         }
 
-        if !state
-            .floors
-            .iter()
-            .take(3)
-            .any(|f| f.microchips.len() > 0 || f.generators.len() > 0)
-        {
-            if args.debug {
-                println!("Part 1: {}", state.steps);
-            } else {
-                progress_bar.finish_with_message(format!("Final steps: {}", state.steps));
+        count = 0;
+        for floor_items in items_on_floor.iter().skip(rules.target_floor + 1).rev() {
+            count += floor_items;
+            if count > 0 {
+                steps += rules.crossings(count);
             }
-            return;
         }
 
-        // The elevator can move up or down and must carry at least one item
-        // If a microchip and a generator are in the same space and the microchip does
-        // not have a matching generator then the state is invalid.
-
-        // Moving up
-        for g in 0..state.floors[state.elevator_floor].generators.len() {
-            // For each generator, we can take the generator, the generator and another generator, or the generator and a matching chip.
-            let mut floors = state.floors.clone();
-            let generator = floors[state.elevator_floor].generators.remove(g);
-
-            // Just the generator up
-            if state.elevator_floor < state.floors.len() - 1 {
-                let mut floors = floors.clone();
-                floors[state.elevator_floor + 1]
-                    .generators
-                    .push(generator.clone());
-                floors[state.elevator_floor + 1].generators.sort();
-
-                states.push(Reverse(State {
-                    steps: state.steps + 1,
-                    floors,
-                    elevator_floor: state.elevator_floor + 1,
-                }));
-            }
+        steps
+    }
 
-            // Just the generator down
-            if state.elevator_floor > 0 {
-                let mut floors = floors.clone();
-                floors[state.elevator_floor - 1]
-                    .generators
-                    .push(generator.clone());
-                floors[state.elevator_floor - 1].generators.sort();
-
-                states.push(Reverse(State {
-                    steps: state.steps + 1,
-                    floors,
-                    elevator_floor: state.elevator_floor - 1,
-                }));
-            }
+    fn successors(&self, rules: &Rules) -> Vec<State> {
+        let elevator_floor = self.elevator_floor();
+        let pairs = self.pairs();
 
-            if floors[state.elevator_floor].microchips.contains(&generator) {
-                let mut floors = floors.clone();
-                floors[state.elevator_floor].microchips = floors[state.elevator_floor]
-                    .microchips
-                    .iter()
-                    .cloned()
-                    .filter(|m| *m != generator)
-                    .collect_vec()
-                    .into();
-
-                // Generator and microchip up
-                if state.elevator_floor < state.floors.len() - 1 {
-                    let mut floors = floors.clone();
-                    floors[state.elevator_floor + 1]
-                        .generators
-                        .push(generator.clone());
-                    floors[state.elevator_floor + 1].generators.sort();
-                    floors[state.elevator_floor + 1]
-                        .microchips
-                        .push(generator.clone());
-                    floors[state.elevator_floor + 1].microchips.sort();
-
-                    states.push(Reverse(State {
-                        steps: state.steps + 1,
-                        floors,
-                        elevator_floor: state.elevator_floor + 1,
-                    }));
+        // Items are (pair index, is microchip)
+        let items = pairs
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (generator, microchip))| {
+                let mut here = Vec::new();
+                if *generator as usize == elevator_floor {
+                    here.push((i, false));
                 }
-
-                // Generator and microchip down
-                if state.elevator_floor > 0 {
-                    floors[state.elevator_floor - 1]
-                        .generators
-                        .push(generator.clone());
-                    floors[state.elevator_floor - 1].generators.sort();
-                    floors[state.elevator_floor - 1]
-                        .microchips
-                        .push(generator.clone());
-                    floors[state.elevator_floor - 1].microchips.sort();
-
-                    states.push(Reverse(State {
-                        steps: state.steps + 1,
-                        floors,
-                        elevator_floor: state.elevator_floor - 1,
-                    }));
+                if *microchip as usize == elevator_floor {
+                    here.push((i, true));
                 }
-            }
+                here
+            })
+            .collect_vec();
+
+        // There's no point taking things away from the target floor when
+        // there's nothing further out to fetch
+        let anything_below = pairs
+            .iter()
+            .any(|(g, m)| (*g as usize) < elevator_floor || (*m as usize) < elevator_floor);
+        let anything_above = pairs
+            .iter()
+            .any(|(g, m)| (*g as usize) > elevator_floor || (*m as usize) > elevator_floor);
+
+        let mut targets = Vec::new();
+        if elevator_floor + 1 < rules.floor_count
+            && (elevator_floor < rules.target_floor || anything_above)
+        {
+            targets.push(elevator_floor + 1);
+        }
+        if elevator_floor > 0 && (elevator_floor > rules.target_floor || anything_below) {
+            targets.push(elevator_floor - 1);
+        }
 
-            // Since we already removed the item at index g, we can start at g again
-            for second_g in g..floors[state.elevator_floor].generators.len() {
-                let mut floors = floors.clone();
-                let second_generator = floors[state.elevator_floor].generators.remove(second_g);
-
-                // Both generators up
-                if state.elevator_floor < state.floors.len() - 1 {
-                    let mut floors = floors.clone();
-                    floors[state.elevator_floor + 1]
-                        .generators
-                        .push(generator.clone());
-                    floors[state.elevator_floor + 1]
-                        .generators
-                        .push(second_generator.clone());
-                    floors[state.elevator_floor + 1].generators.sort();
-
-                    states.push(Reverse(State {
-                        steps: state.steps + 1,
-                        floors,
-                        elevator_floor: state.elevator_floor + 1,
-                    }));
+        let mut successors = Vec::new();
+        for target in targets {
+            // The elevator must carry at least one item
+            for carried in (1..=rules.capacity).flat_map(|n| items.iter().combinations(n)) {
+                let mut next = pairs.clone();
+                for (i, microchip) in carried {
+                    if *microchip {
+                        next[*i].1 = target as u8;
+                    } else {
+                        next[*i].0 = target as u8;
+                    }
                 }
 
-                // Both generators down
-                if state.elevator_floor > 0 {
-                    let mut floors = floors.clone();
-                    floors[state.elevator_floor - 1]
-                        .generators
-                        .push(generator.clone());
-                    floors[state.elevator_floor - 1]
-                        .generators
-                        .push(second_generator.clone());
-                    floors[state.elevator_floor - 1].generators.sort();
-
-                    states.push(Reverse(State {
-                        steps: state.steps + 1,
-                        floors,
-                        elevator_floor: state.elevator_floor - 1,
-                    }));
+                let state = State::new(target, &mut next);
+                if state.is_valid(rules.safety) {
+                    successors.push(state);
                 }
             }
         }
 
-        // Just moving 1 or two microchips
-        for m in 0..state.floors[state.elevator_floor].microchips.len() {
-            let mut floors = state.floors.clone();
-            let microchip = floors[state.elevator_floor].microchips.remove(m);
-
-            // Just the microchip up
-            if state.elevator_floor < state.floors.len() - 1 {
-                let mut floors = floors.clone();
-                floors[state.elevator_floor + 1]
-                    .microchips
-                    .push(microchip.clone());
-                floors[state.elevator_floor + 1].microchips.sort();
-
-                states.push(Reverse(State {
-                    steps: state.steps + 1,
-                    floors,
-                    elevator_floor: state.elevator_floor + 1,
-                }));
-            }
+        successors
+    }
+}
 
-            // Just the microchip down
-            if state.elevator_floor > 0 {
-                let mut floors = floors.clone();
-                floors[state.elevator_floor - 1]
-                    .microchips
-                    .push(microchip.clone());
-                floors[state.elevator_floor - 1].microchips.sort();
-
-                states.push(Reverse(State {
-                    steps: state.steps + 1,
-                    floors,
-                    elevator_floor: state.elevator_floor - 1,
-                }));
-            }
+fn main() {
+    let args = Args::parse();
 
-            // Since we already removed the item at index m, we can start at g again
-            for second_m in m..floors[state.elevator_floor].microchips.len() {
-                let mut floors = floors.clone();
-                let second_microchip = floors[state.elevator_floor].microchips.remove(second_m);
-
-                // Both microchips up
-                if state.elevator_floor < state.floors.len() - 1 {
-                    let mut floors = floors.clone();
-                    floors[state.elevator_floor + 1]
-                        .microchips
-                        .push(microchip.clone());
-                    floors[state.elevator_floor + 1]
-                        .microchips
-                        .push(second_microchip.clone());
-                    floors[state.elevator_floor + 1].microchips.sort();
-
-                    states.push(Reverse(State {
-                        steps: state.steps + 1,
-                        floors,
-                        elevator_floor: state.elevator_floor + 1,
-                    }));
-                }
+    let mut floors = layout::load(&args.data_file).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    });
+    for pair in args.add_pair.iter() {
+        if let Err(error) = layout::add_pair(&mut floors, pair) {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    }
+    if let Some(floor_count) = args.floors {
+        if floor_count < floors.len() {
+            eprintln!("The layout already has {} floors", floors.len());
+            std::process::exit(1);
+        }
+        floors.resize(floor_count, Floor::default());
+    }
+
+    let target_floor = args.target_floor.unwrap_or(floors.len());
+    let Some(target_floor) = target_floor.checked_sub(1) else {
+        eprintln!(
+            "Floors are numbered from 1, so there's no floor {}",
+            target_floor
+        );
+        std::process::exit(1);
+    };
+    let rules = Rules {
+        capacity: args.capacity,
+        floor_count: floors.len(),
+        target_floor,
+        safety: args.safety,
+    };
+    if rules.capacity == 0 || rules.target_floor >= rules.floor_count {
+        eprintln!("Invalid capacity or target floor for {:?}", rules);
+        std::process::exit(1);
+    }
+
+    if args.print_layout {
+        for (index, floor) in floors.iter().enumerate() {
+            println!("{}", floor.to_puzzle_text(index));
+        }
+    }
+
+    let progress_bar = indicatif::ProgressBar::new_spinner();
+    let start = State::from_floors(&floors, 0).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    });
+    let Some(path) = find_path(start, &rules, args.debug, &progress_bar) else {
+        panic!("No way to get everything to the target floor");
+    };
+
+    let steps = path.len() - 1;
+    if args.debug {
+        println!("Part 1: {}", steps);
+    } else {
+        progress_bar.finish_with_message(format!("Final steps: {}", steps));
+    }
 
-                // Both microchips down
-                if state.elevator_floor > 0 {
-                    let mut floors = floors.clone();
-                    floors[state.elevator_floor - 1]
-                        .microchips
-                        .push(microchip.clone());
-                    floors[state.elevator_floor - 1]
-                        .microchips
-                        .push(second_microchip.clone());
-                    floors[state.elevator_floor - 1].microchips.sort();
-
-                    states.push(Reverse(State {
-                        steps: state.steps + 1,
-                        floors,
-                        elevator_floor: state.elevator_floor - 1,
-                    }));
+    if args.show_moves || args.verify {
+        let moves = to_moves(&floors, &path);
+
+        let mut layout = floors.clone();
+        let mut elevator_floor = 0;
+        if args.show_moves {
+            print_layout(&layout, elevator_floor);
+        }
+        for (step, m) in moves.iter().enumerate() {
+            if args.verify {
+                if m.from != elevator_floor {
+                    panic!(
+                        "Step {}: the elevator isn't on floor {}",
+                        step + 1,
+                        m.from + 1
+                    );
                 }
+                if let Err(error) = m.apply(&mut layout, &rules) {
+                    panic!("Step {}: {}", step + 1, error);
+                }
+            } else {
+                m.carry(&mut layout);
+            }
+            elevator_floor = m.to;
+
+            if args.show_moves {
+                println!("Step {}: {}", step + 1, m);
+                print_layout(&layout, elevator_floor);
             }
         }
+
+        if args.verify {
+            if layout
+                .iter()
+                .enumerate()
+                .any(|(floor, f)| floor != rules.target_floor && !f.items().is_empty())
+            {
+                panic!("The moves don't get everything to the target floor");
+            }
+            println!("Verified {} moves", moves.len());
+        }
     }
 }
 
-fn to_floor(line: &String) -> Floor {
-    let mut generators = Vec::new();
-    let mut microchips = Vec::new();
+/// A* over the packed states, returning every state from `start` to the
+/// finished state.
+fn find_path(
+    start: State,
+    rules: &Rules,
+    debug: bool,
+    progress_bar: &indicatif::ProgressBar,
+) -> Option<Vec<State>> {
+    // Ordered by the estimated total steps, then by the steps taken so far
+    let mut states: BinaryHeap<Reverse<(u32, u32, State, State)>> = BinaryHeap::new();
+    states.push(Reverse((start.steps_to_finish(rules), 0, start, start)));
+    // Each state we've expanded and the state we reached it from
+    let mut parents = HashMap::new();
+
+    while let Some(Reverse((_, steps, state, parent))) = states.pop() {
+        if parents.contains_key(&state) {
+            continue;
+        }
+        parents.insert(state, parent);
 
-    let components = line.split_whitespace().collect_vec();
-    let mut index = 5;
-    while index < components.len() - 1 {
-        // This means there was an extra word, "and" for this entry
-        if components[index] == "a" {
-            index += 1;
+        if debug {
+            println!("Step: {}, States: {}", steps, states.len());
+        } else {
+            progress_bar.set_message(format!("Step: {}, States: {}", steps, states.len()));
         }
-        match components[index + 1]
-            .trim_end_matches(",")
-            .trim_end_matches(".")
-        {
-            "generator" => generators.push(components[index].to_string()),
-            "microchip" => microchips.push(
-                components[index]
-                    .trim_end_matches("-compatible")
-                    .to_string(),
-            ),
-            _ => panic!("Unexpected string"),
+
+        if state.is_finished(rules.target_floor) {
+            let mut path = vec![state];
+            let mut current = state;
+            while current != start {
+                current = parents[&current];
+                path.push(current);
+            }
+            path.reverse();
+            return Some(path);
         }
 
-        index += 3;
+        for next in state.successors(rules) {
+            if !parents.contains_key(&next) {
+                states.push(Reverse((
+                    steps + 1 + next.steps_to_finish(rules),
+                    steps + 1,
+                    next,
+                    state,
+                )));
+            }
+        }
     }
 
-    Floor {
-        microchips,
-        generators,
+    None
+}
+
+/// The packed states don't know element names, so work out which named items
+/// were carried by trying each load against the next state in the path. Only
+/// the states are compared; whether the moves are allowed is left to
+/// `--verify`.
+fn to_moves(floors: &[Floor], path: &[State]) -> Vec<Move> {
+    let mut layout = floors.to_vec();
+    let mut moves = Vec::new();
+
+    for (current, next) in path.iter().tuple_windows() {
+        let from = current.elevator_floor();
+        let to = next.elevator_floor();
+        let items = layout[from].items();
+
+        let m = (1..=items.len())
+            .flat_map(|n| items.iter().cloned().combinations(n))
+            .map(|items| Move { from, to, items })
+            .find(|m| {
+                let mut candidate = layout.clone();
+                m.carry(&mut candidate);
+                State::from_floors(&candidate, to) == Ok(*next)
+            })
+            .expect("Path steps should always be a single elevator move");
+
+        m.carry(&mut layout);
+        moves.push(m);
+    }
+
+    moves
+}
+
+fn print_layout(floors: &[Floor], elevator_floor: usize) {
+    for (number, floor) in floors.iter().enumerate().rev() {
+        let line = format!(
+            "F{} {} {}",
+            number + 1,
+            if number == elevator_floor { "E" } else { "." },
+            floor.items().iter().join(", ")
+        );
+        println!("{}", line.trim_end());
     }
 }
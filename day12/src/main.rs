@@ -1,84 +1,28 @@
-use clap::Parser;
-use itertools::Itertools;
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-    vec,
+use assembunny::{
+    cli::{ProgramArgs, RunArgs},
+    Machine, Native, NativeExit, Register,
 };
-
-#[derive(Clone, Copy)]
-#[repr(usize)]
-enum Register {
-    A = 0,
-    B,
-    C,
-    D,
-}
-
-impl From<&str> for Register {
-    fn from(value: &str) -> Self {
-        match value {
-            "a" => Register::A,
-            "b" => Register::B,
-            "c" => Register::C,
-            "d" => Register::D,
-            _ => panic!("Unexpected register"),
-        }
-    }
-}
-
-#[derive(Clone, Copy)]
-enum Operand {
-    Value(i32),
-    Register(Register),
-}
-
-impl From<&str> for Operand {
-    fn from(value: &str) -> Self {
-        if let Ok(value) = value.parse::<i32>() {
-            Operand::Value(value)
-        } else {
-            Operand::Register(value.into())
-        }
-    }
-}
-
-#[derive(Clone, Copy)]
-enum Instruction {
-    Copy(Operand, Register),
-    Increment(Register),
-    Decrement(Register),
-    JumpNotZero(Operand, i32),
-}
-
-impl From<&str> for Instruction {
-    fn from(value: &str) -> Self {
-        let components = value.split_whitespace().collect_vec();
-
-        match components[0] {
-            "cpy" => Instruction::Copy(components[1].into(), components[2].into()),
-            "inc" => Instruction::Increment(components[1].into()),
-            "dec" => Instruction::Decrement(components[1].into()),
-            "jnz" => Instruction::JumpNotZero(
-                components[1].into(),
-                // We subtract one here so we can always increment the program counter
-                components[2]
-                    .parse::<i32>()
-                    .expect("Failed to parse jump target")
-                    - 1,
-            ),
-            _ => panic!("Unexpected instruction"),
-        }
-    }
-}
+use clap::Parser;
+use std::ops::ControlFlow;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     #[arg(long)]
     data_file: String,
+    #[command(flatten)]
+    program: ProgramArgs,
+    #[command(flatten)]
+    run: RunArgs,
     #[arg(long)]
     debug: bool,
+    /// Print the program as a Rust function instead of running it
+    #[arg(long)]
+    transpile: bool,
+    /// Compile the program with rustc and run that, programs using tgl still
+    /// run in the interpreter. It can't be profiled, traced or limited
+    #[arg(long, conflicts_with_all = ["max_steps", "timeout", "profile", "trace", "interactive"])]
+    native: bool,
     #[arg(long)]
     part2: bool,
 }
@@ -86,50 +30,62 @@ struct Args {
 fn main() {
     let args = Args::parse();
 
-    let file = File::open(&args.data_file).expect("Failed to open file");
-    let reader = BufReader::new(file);
-    let lines: Vec<String> = reader
-        .lines()
-        .map(|line| line.expect("Failed to read line"))
-        .collect();
+    let instructions = assembunny::load_program(&args.data_file).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    });
+    let mut machine = Machine::new(instructions.clone());
+    args.program.prepare(&mut machine);
 
-    let instructions = lines
-        .iter()
-        .map(|line| Instruction::from(line.as_str()))
-        .collect_vec();
+    if args.program.decompile {
+        print!("{}", assembunny::decompile(machine.instructions()));
+        return;
+    }
 
-    let mut pc: i32 = 0;
-    let mut registers = vec![0; 4];
+    if args.transpile {
+        match assembunny::transpile(&instructions, args.run.width) {
+            Ok(source) => print!("{}", source),
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
     if args.part2 {
-        registers[2] = 1;
+        machine.set_register(Register::C, 1);
+    }
+    if let Err(error) = args.run.apply(&mut machine) {
+        eprintln!("{}", error);
+        std::process::exit(1);
     }
 
-    while (pc as usize) < instructions.len() {
-        match &instructions[pc as usize] {
-            Instruction::Copy(src, dst) => {
-                registers[*dst as usize] = match src {
-                    Operand::Value(val) => *val,
-                    Operand::Register(src) => registers[*src as usize],
-                }
+    if args.native {
+        let directory = assembunny::build_directory();
+        let result = Native::build(&instructions, args.run.width, &directory).and_then(|native| {
+            native.run(machine.registers(), |value, _, _| {
+                println!("{}", value);
+                ControlFlow::Continue(())
+            })
+        });
+        let _ = std::fs::remove_dir_all(&directory);
+        match result {
+            Ok(NativeExit::Halted(pc, registers)) => {
+                args.run.report_native(&registers, pc);
+                return;
             }
-            Instruction::Increment(reg) => registers[*reg as usize] += 1,
-            Instruction::Decrement(reg) => registers[*reg as usize] -= 1,
-            Instruction::JumpNotZero(operand, dst) => {
-                if match operand {
-                    Operand::Value(val) => *val,
-                    Operand::Register(reg) => registers[*reg as usize],
-                } != 0
-                {
-                    pc += *dst;
-                }
+            Ok(NativeExit::Overflowed(..)) => {
+                // The interpreter says where and why
+                eprintln!("The native program overflowed, running in the interpreter")
             }
+            Ok(NativeExit::Stopped) => unreachable!("The output never stops the program"),
+            Err(error) => eprintln!("{}, running in the interpreter", error),
         }
-
-        pc += 1;
     }
 
-    print!("Registers: ");
-    registers.iter().for_each(|reg| print!("{} ", reg));
-    println!("");
+    if let Err(error) = args.run.execute(&mut machine, args.debug) {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
 }
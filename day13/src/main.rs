@@ -1,175 +1,278 @@
 use clap::Parser;
+use grid::{bfs, Grid, Point, Visit};
 use itertools::Itertools;
 use std::{
-    cmp::Reverse,
-    collections::{BinaryHeap, VecDeque},
+    collections::HashSet,
+    fs::File,
+    io::{BufWriter, Write},
 };
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     #[arg(long)]
-    input: u32,
+    input: u64,
+    /// Where to start, as X,Y
+    #[arg(long, default_value = "1,1", value_parser = parse_position)]
+    start: Point,
+    /// Where to get to, as X,Y. Repeat it to find several at once
+    #[arg(long, default_values = ["31,39"], value_parser = parse_position)]
+    target: Vec<Point>,
+    /// The step budget for Part 2
+    #[arg(long, default_value_t = 50)]
+    max_steps: u32,
+    /// Print how many cells are within each number of steps, up to the
+    /// budget, and the farthest ones
+    #[arg(long)]
+    histogram: bool,
+    /// Draw the maze from 0,0 to this size, as WxH, with the shortest paths
+    /// and the cells reachable within the step budget
+    #[arg(long, value_parser = parse_size)]
+    render: Option<(usize, usize)>,
+    /// Write the rendering to this PPM image rather than the terminal
+    #[arg(long, requires = "render")]
+    image: Option<String>,
     #[arg(long)]
     debug: bool,
 }
 
-#[derive(PartialEq, Eq, Clone, Copy, Hash)]
-struct State {
-    steps: u32,
-    position: (u32, u32),
-    target: (u32, u32),
+/// What a cell is drawn as, from the bottom layer up.
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Cell {
+    Wall,
+    Open,
+    Reachable,
+    Path,
+    End,
 }
 
-impl PartialOrd for State {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match (self.steps
-            + self.position.0.abs_diff(self.target.0)
-            + self.position.1.abs_diff(self.target.1))
-        .partial_cmp(
-            &(other.steps
-                + other.position.0.abs_diff(other.target.0)
-                + other.position.1.abs_diff(other.target.1)),
-        ) {
-            Some(core::cmp::Ordering::Equal) => {}
-            ord => return ord,
-        }
+/// Pixels per cell in an image.
+const SCALE: usize = 8;
 
-        match self.steps.partial_cmp(&other.steps) {
-            Some(core::cmp::Ordering::Equal) => {}
-            ord => return ord,
-        }
-        self.position.partial_cmp(&other.position)
-    }
+/// The office, whose walls come from the favourite number.
+struct Office {
+    input: u64,
 }
 
-impl Ord for State {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+impl Grid for Office {
+    fn is_open(&self, point: Point) -> bool {
+        !is_wall(point, self.input)
     }
 }
 
-fn main() {
-    let args = Args::parse();
-
-    do_part1(&args);
-    do_part2(&args);
+fn parse_position(text: &str) -> Result<Point, String> {
+    let (x, y) = text
+        .split_once(',')
+        .ok_or_else(|| format!("Expected X,Y, got \"{}\"", text))?;
+    // Keeping coordinates to a u32 means is_wall's u128 arithmetic can't
+    // overflow, however far the search goes past them
+    let coordinate = |value: &str| {
+        value
+            .trim()
+            .parse::<u32>()
+            .map(|value| value as usize)
+            .map_err(|_| {
+                format!(
+                    "Invalid coordinate \"{}\", they go from 0 to {}",
+                    value,
+                    u32::MAX
+                )
+            })
+    };
+    Ok((coordinate(x)?, coordinate(y)?))
 }
 
-fn do_part1(args: &Args) {
-    let mut states: BinaryHeap<Reverse<State>> = BinaryHeap::new();
-    states.push(Reverse(State {
-        position: (1, 1),
-        steps: 0,
-        target: (31, 39),
-    }));
-
-    let mut positions_seen = Vec::new();
-
-    while !states.is_empty() {
-        let state = states.pop().unwrap().0;
-
-        if positions_seen.contains(&state.position) {
-            continue;
-        }
-
-        positions_seen.push(state.position);
-
-        if state.position == state.target {
-            println!("Part 1: {}", state.steps);
-            break;
-        }
-
-        let mut state = state.clone();
-        state.steps += 1;
+fn parse_size(text: &str) -> Result<(usize, usize), String> {
+    let (width, height) = text
+        .split_once('x')
+        .ok_or_else(|| format!("Expected WxH, got \"{}\"", text))?;
+    let dimension = |value: &str| match value.trim().parse() {
+        Ok(0) | Err(_) => Err(format!("Invalid size \"{}\"", value)),
+        Ok(value) => Ok(value),
+    };
+    Ok((dimension(width)?, dimension(height)?))
+}
 
-        if state.position.0 > 0 && !is_wall((state.position.0 - 1, state.position.1), args.input) {
-            let mut state = state.clone();
-            state.position.0 -= 1;
-            states.push(Reverse(state));
-        }
+fn main() {
+    let args = Args::parse();
 
-        if state.position.1 > 0 && !is_wall((state.position.0, state.position.1 - 1), args.input) {
-            let mut state = state.clone();
-            state.position.1 -= 1;
-            states.push(Reverse(state));
-        }
+    if is_wall(args.start, args.input) {
+        eprintln!("The start {:?} is a wall", args.start);
+        std::process::exit(1);
+    }
 
-        if !is_wall((state.position.0, state.position.1 + 1), args.input) {
-            let mut state = state.clone();
-            state.position.1 += 1;
-            states.push(Reverse(state));
-        }
+    let path = do_part1(&args);
+    let reachable = do_part2(&args);
 
-        if !is_wall((state.position.0 + 1, state.position.1), args.input) {
-            let mut state = state.clone();
-            state.position.0 += 1;
-            states.push(Reverse(state));
-        }
+    if let Some(size) = args.render {
+        render(&args, size, &path, &reachable);
     }
 }
 
-fn do_part2(args: &Args) {
-    let mut states: VecDeque<State> = VecDeque::new();
-    states.push_back(State {
-        position: (1, 1),
-        steps: 0,
-        // Target is irrelevant in this mode
-        target: (1, 1),
-    });
+/// Finds the shortest distance to every target with a single breadth-first
+/// search, which stops once they've all been reached. Returns the cells on
+/// the shortest paths to them.
+fn do_part1(args: &Args) -> HashSet<Point> {
+    let office = Office { input: args.input };
+    let mut remaining = args
+        .target
+        .iter()
+        .copied()
+        .filter(|target| office.is_open(*target))
+        .collect_vec();
+    let paths = bfs(
+        args.start,
+        |point| office.neighbours(*point),
+        |point, _| {
+            remaining.retain(|target| target != point);
+            if remaining.is_empty() {
+                Visit::Stop
+            } else {
+                Visit::Expand
+            }
+        },
+    );
 
-    let mut positions_seen = Vec::new();
+    for target in args.target.iter() {
+        let answer = match paths.cost(target) {
+            Some(steps) => steps.to_string(),
+            None if is_wall(*target, args.input) => "a wall".to_string(),
+            None => "unreachable".to_string(),
+        };
+        if args.target.len() == 1 {
+            println!("Part 1: {}", answer);
+        } else {
+            println!("Part 1: {},{}: {}", target.0, target.1, answer);
+        }
+    }
 
-    while !states.is_empty() {
-        let state = states.pop_front().unwrap();
+    args.target
+        .iter()
+        .filter_map(|target| paths.path_to(target))
+        .flatten()
+        .chain([args.start])
+        .collect()
+}
 
-        if positions_seen.contains(&state.position) {
-            continue;
-        }
+/// Finds every cell within the step budget with one breadth-first search.
+fn do_part2(args: &Args) -> HashSet<Point> {
+    let office = Office { input: args.input };
+    let max_steps = args.max_steps as usize;
+    let paths = bfs(
+        args.start,
+        |point| office.neighbours(*point),
+        |_, steps| {
+            if steps == max_steps {
+                Visit::Skip
+            } else {
+                Visit::Expand
+            }
+        },
+    );
 
-        positions_seen.push(state.position);
+    println!("Part 2: {}", paths.len());
 
-        if state.steps == 50 {
-            continue;
+    if args.histogram {
+        let counts = paths.reached().map(|(_, steps)| steps).counts();
+        let farthest = *counts.keys().max().unwrap();
+        println!("Steps  Cells");
+        let mut total = 0;
+        for steps in 0..=farthest {
+            total += counts[&steps];
+            println!("{:>5}  {:>5}", steps, total);
         }
+        println!(
+            "Farthest, {} steps: {}",
+            farthest,
+            paths
+                .reached()
+                .filter(|(_, steps)| *steps == farthest)
+                .map(|(point, _)| *point)
+                .sorted()
+                .map(|(x, y)| format!("{},{}", x, y))
+                .join(" ")
+        );
+    }
 
-        let mut state = state.clone();
-        state.steps += 1;
+    paths.reached().map(|(point, _)| *point).collect()
+}
 
-        if state.position.0 > 0 && !is_wall((state.position.0 - 1, state.position.1), args.input) {
-            let mut state = state.clone();
-            state.position.0 -= 1;
-            states.push_back(state);
+fn render(args: &Args, size: (usize, usize), path: &HashSet<Point>, reachable: &HashSet<Point>) {
+    let (width, height) = size;
+    let cell = |position: Point| {
+        if position == args.start || args.target.contains(&position) {
+            Cell::End
+        } else if is_wall(position, args.input) {
+            Cell::Wall
+        } else if path.contains(&position) {
+            Cell::Path
+        } else if reachable.contains(&position) {
+            Cell::Reachable
+        } else {
+            Cell::Open
         }
+    };
 
-        if state.position.1 > 0 && !is_wall((state.position.0, state.position.1 - 1), args.input) {
-            let mut state = state.clone();
-            state.position.1 -= 1;
-            states.push_back(state);
+    match &args.image {
+        Some(filename) => {
+            let mut image = BufWriter::new(File::create(filename).unwrap_or_else(|error| {
+                eprintln!("Failed to create {}: {}", filename, error);
+                std::process::exit(1);
+            }));
+            write_image(&mut image, size, cell)
+                .and_then(|_| image.flush())
+                .unwrap_or_else(|error| {
+                    eprintln!("Failed to write {}: {}", filename, error);
+                    std::process::exit(1);
+                });
         }
-
-        if !is_wall((state.position.0, state.position.1 + 1), args.input) {
-            let mut state = state.clone();
-            state.position.1 += 1;
-            states.push_back(state);
+        None => {
+            for y in 0..height {
+                let line: String = (0..width)
+                    .map(|x| match cell((x, y)) {
+                        Cell::Wall => "\x1b[0;90m#",
+                        Cell::Open => "\x1b[0m.",
+                        Cell::Reachable => "\x1b[0;44m.",
+                        Cell::Path if reachable.contains(&(x, y)) => "\x1b[44;93mO",
+                        Cell::Path => "\x1b[0;93mO",
+                        Cell::End => "\x1b[0;92m@",
+                    })
+                    .collect();
+                println!("{}\x1b[0m", line);
+            }
         }
+    }
+}
 
-        if !is_wall((state.position.0 + 1, state.position.1), args.input) {
-            let mut state = state.clone();
-            state.position.0 += 1;
-            states.push_back(state);
+/// Writes a binary PPM with `SCALE` pixels to a cell.
+fn write_image(
+    image: &mut impl Write,
+    (width, height): (usize, usize),
+    cell: impl Fn(Point) -> Cell,
+) -> std::io::Result<()> {
+    write!(image, "P6\n{} {}\n255\n", width * SCALE, height * SCALE)?;
+    for y in 0..height {
+        let row = (0..width)
+            .flat_map(|x| {
+                let colour = match cell((x, y)) {
+                    Cell::Wall => [64, 64, 64],
+                    Cell::Open => [255, 255, 255],
+                    Cell::Reachable => [160, 200, 255],
+                    Cell::Path => [255, 160, 0],
+                    Cell::End => [0, 160, 0],
+                };
+                std::iter::repeat_n(colour, SCALE).flatten()
+            })
+            .collect_vec();
+        for _ in 0..SCALE {
+            image.write_all(&row)?;
         }
     }
-
-    println!("Part 2: {}", positions_seen.len());
+    Ok(())
 }
 
-fn is_wall(position: (u32, u32), input: u32) -> bool {
-    let test = position.0.pow(2)
-        + position.0 * 3
-        + position.0 * position.1 * 2
-        + position.1
-        + position.1.pow(2)
-        + input;
+fn is_wall(point: Point, input: u64) -> bool {
+    let (x, y) = (point.0 as u128, point.1 as u128);
+    let test = x * x + 3 * x + 2 * x * y + y + y * y + input as u128;
     test.count_ones() % 2 == 1
 }
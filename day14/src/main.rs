@@ -1,8 +1,13 @@
-use std::collections::{HashSet, VecDeque};
+use std::{
+    collections::{HashSet, VecDeque},
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+};
 
 use clap::Parser;
 use indicatif::ProgressBar;
 use itertools::Itertools;
+use rayon::prelude::*;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -13,12 +18,122 @@ struct Args {
     debug: bool,
     #[arg(long)]
     part2: bool,
+    /// Keep the hashes in this directory, a file per salt, so later runs
+    /// with the same salt don't have to work them out again
+    #[arg(long)]
+    cache: Option<String>,
+}
+
+/// How many hashes are worked out at once, ahead of the scanner. Checking a
+/// triple needs the next 1000 anyway.
+const WINDOW: usize = 1000;
+
+/// The hashes by index, worked out in parallel a window at a time.
+struct Hashes {
+    salt: String,
+    rounds: usize,
+    digests: Vec<[u8; 16]>,
+    cache: Option<File>,
+}
+
+impl Hashes {
+    fn new(args: &Args) -> Hashes {
+        let rounds = if args.part2 { 2017 } else { 1 };
+        let mut hashes = Hashes {
+            salt: args.salt.clone(),
+            rounds,
+            digests: Vec::new(),
+            cache: None,
+        };
+        if let Some(directory) = &args.cache {
+            hashes.open_cache(directory).unwrap_or_else(|error| {
+                eprintln!("Failed to open the cache in {}: {}", directory, error);
+                std::process::exit(1);
+            });
+        }
+        hashes
+    }
+
+    /// Loads what's already in the cache and leaves it open to add to.
+    fn open_cache(&mut self, directory: &str) -> std::io::Result<()> {
+        std::fs::create_dir_all(directory)?;
+        let name = self
+            .salt
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_string(),
+                // Fixed width, so no two salts share a name
+                _ => format!("%{:06x}", c as u32),
+            })
+            .join("");
+        let path = std::path::Path::new(directory).join(format!("{}-{}.md5", name, self.rounds));
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        self.digests = contents
+            .chunks_exact(16)
+            .map(|digest| digest.try_into().unwrap())
+            .collect();
+        // Drop anything left half written
+        file.set_len(self.digests.len() as u64 * 16)?;
+        file.seek(SeekFrom::End(0))?;
+        self.cache = Some(file);
+        Ok(())
+    }
+
+    /// The hash at an index, in hex.
+    fn get(&mut self, index: usize) -> [u8; 32] {
+        while index >= self.digests.len() {
+            let start = self.digests.len();
+            let window: Vec<[u8; 16]> = (start..start + WINDOW)
+                .into_par_iter()
+                .map(|i| stretch(&self.salt, i, self.rounds))
+                .collect();
+            if let Some(cache) = &mut self.cache {
+                cache
+                    .write_all(window.concat().as_slice())
+                    .unwrap_or_else(|error| {
+                        eprintln!("Failed to write to the cache: {}", error);
+                        std::process::exit(1);
+                    });
+            }
+            self.digests.extend(window);
+        }
+        to_hex(&self.digests[index])
+    }
+}
+
+/// The salted index hashed `rounds` times, each round hashing the hex of
+/// the one before.
+fn stretch(salt: &str, index: usize, rounds: usize) -> [u8; 16] {
+    let mut digest = md5::compute(format!("{}{}", salt, index)).0;
+    for _ in 1..rounds {
+        digest = md5::compute(to_hex(&digest)).0;
+    }
+    digest
+}
+
+fn to_hex(digest: &[u8; 16]) -> [u8; 32] {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut hex = [0; 32];
+    for (i, byte) in digest.iter().enumerate() {
+        hex[2 * i] = DIGITS[(byte >> 4) as usize];
+        hex[2 * i + 1] = DIGITS[(byte & 0xf) as usize];
+    }
+    hex
 }
 
 fn main() {
     let args = Args::parse();
 
     let progress = ProgressBar::new_spinner();
+    let mut hashes = Hashes::new(&args);
 
     let mut i = 0;
     let mut seen_triples: VecDeque<(i32, char)> = VecDeque::new();
@@ -26,17 +141,7 @@ fn main() {
     'hashing: loop {
         progress.tick();
         progress.set_message(format!("{}", i));
-        let mut digest = md5::compute(format!("{}{}", args.salt, i.to_string()));
-        let mut hashed = format!("{:x}", digest);
-
-        if args.part2 {
-            for _ in 0..2016 {
-                digest = md5::compute(hashed);
-                hashed = format!("{:x}", digest);
-            }
-        }
-
-        let (triple, quintuples) = get_repeats(&hashed);
+        let (triple, quintuples) = get_repeats(&hashes.get(i as usize));
 
         loop {
             if !seen_triples.is_empty() && seen_triples.front().unwrap().0 < i - 1000 {
@@ -70,12 +175,12 @@ fn main() {
     }
 }
 
-fn get_repeats(digest: &str) -> (Option<char>, Vec<char>) {
+fn get_repeats(digest: &[u8]) -> (Option<char>, Vec<char>) {
     let mut last_char = 'Z';
     let mut last_char_count = 0;
     let mut triple = None;
     let mut quintuples = Vec::new();
-    for c in digest.chars() {
+    for c in digest.iter().map(|c| *c as char) {
         if c == last_char {
             last_char_count += 1;
             if last_char_count == 3 && triple.is_none() {
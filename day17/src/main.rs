@@ -1,7 +1,5 @@
-use std::{cmp::Reverse, collections::BinaryHeap};
-
 use clap::Parser;
-use itertools::Itertools;
+use grid::{astar, bfs, Point, Visit};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -12,35 +10,10 @@ struct Args {
     debug: bool,
 }
 
-#[derive(PartialEq, Eq)]
-struct State {
-    position: (i8, i8),
-    code: String,
-}
-
-impl PartialOrd for State {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        match (self.position.0 as isize + self.position.1 as isize - self.code.len() as isize)
-            .partial_cmp(
-                &(other.position.0 as isize + other.position.1 as isize
-                    - other.code.len() as isize),
-            ) {
-            Some(core::cmp::Ordering::Equal) => {}
-            ord => return ord,
-        }
-        match self.position.partial_cmp(&other.position) {
-            Some(core::cmp::Ordering::Equal) => {}
-            ord => return ord,
-        }
-        self.code.partial_cmp(&other.code)
-    }
-}
+/// Where we are and the passcode with the path so far on the end.
+type State = (Point, String);
 
-impl Ord for State {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
-    }
-}
+const VAULT: Point = (3, 3);
 
 fn main() {
     let args = Args::parse();
@@ -50,92 +23,55 @@ fn main() {
 }
 
 fn do_part1(args: &Args) {
-    let mut states = BinaryHeap::new();
-    states.push(State {
-        position: (0, 0),
-        code: args.input.clone(),
-    });
-
-    while !states.is_empty() {
-        let state = states.pop().unwrap();
-
-        if state.position == (3, 3) {
-            println!("Part 1: {}", state.code.trim_start_matches(&args.input));
-            break;
-        }
-
-        let hash = md5::compute(state.code.clone()).0;
-        if state.position.1 > 0 && hash[0] >= 0xb0 {
-            states.push(State {
-                position: (state.position.0, state.position.1 - 1),
-                code: state.code.clone() + "U",
-            });
-        }
-        if state.position.1 < 3 && hash[0] & 0x0F >= 0x0b {
-            states.push(State {
-                position: (state.position.0, state.position.1 + 1),
-                code: state.code.clone() + "D",
-            });
-        }
-
-        if state.position.0 > 0 && hash[1] >= 0xb0 {
-            states.push(State {
-                position: (state.position.0 - 1, state.position.1),
-                code: state.code.clone() + "L",
-            });
-        }
-        if state.position.0 < 3 && hash[1] & 0x0F >= 0x0b {
-            states.push(State {
-                position: (state.position.0 + 1, state.position.1),
-                code: state.code.clone() + "R",
-            });
-        }
+    let found = astar(
+        ((0, 0), args.input.clone()),
+        |state| doors(state).into_iter().map(|next| (next, 1)),
+        |((x, y), _)| (VAULT.0 - x) + (VAULT.1 - y),
+        |(position, _)| *position == VAULT,
+    );
+
+    if let Some((_, path)) = found {
+        let (_, code) = path.last().unwrap();
+        println!("Part 1: {}", code.trim_start_matches(&args.input));
     }
 }
 
 fn do_part2(args: &Args) {
-    let mut states = BinaryHeap::new();
-    states.push(State {
-        position: (0, 0),
-        code: args.input.clone(),
-    });
-
     let mut longest_path = 0;
-    while !states.is_empty() {
-        let state = states.pop().unwrap();
+    bfs(
+        ((0, 0), args.input.clone()),
+        doors,
+        |(position, _), steps| {
+            if *position == VAULT {
+                // The path ends as soon as it gets to the vault
+                longest_path = longest_path.max(steps);
+                Visit::Skip
+            } else {
+                Visit::Expand
+            }
+        },
+    );
 
-        if state.position == (3, 3) {
-            longest_path = longest_path.max(state.code.trim_start_matches(&args.input).len());
-            continue;
-        }
+    println!("Part 2: {}", longest_path);
+}
 
-        let hash = md5::compute(state.code.clone()).0;
-        if state.position.1 > 0 && hash[0] >= 0xb0 {
-            states.push(State {
-                position: (state.position.0, state.position.1 - 1),
-                code: state.code.clone() + "U",
-            });
-        }
-        if state.position.1 < 3 && hash[0] & 0x0F >= 0x0b {
-            states.push(State {
-                position: (state.position.0, state.position.1 + 1),
-                code: state.code.clone() + "D",
-            });
-        }
+/// The rooms we can go to next, through the doors the hash says are open.
+fn doors(state: &State) -> Vec<State> {
+    let ((x, y), code) = state;
+    let hash = md5::compute(code).0;
 
-        if state.position.0 > 0 && hash[1] >= 0xb0 {
-            states.push(State {
-                position: (state.position.0 - 1, state.position.1),
-                code: state.code.clone() + "L",
-            });
-        }
-        if state.position.0 < 3 && hash[1] & 0x0F >= 0x0b {
-            states.push(State {
-                position: (state.position.0 + 1, state.position.1),
-                code: state.code.clone() + "R",
-            });
-        }
+    let mut next = Vec::new();
+    if *y > 0 && hash[0] >= 0xb0 {
+        next.push(((*x, y - 1), code.clone() + "U"));
     }
-
-    println!("Part 2: {}", longest_path);
+    if *y < 3 && hash[0] & 0x0F >= 0x0b {
+        next.push(((*x, y + 1), code.clone() + "D"));
+    }
+    if *x > 0 && hash[1] >= 0xb0 {
+        next.push(((x - 1, *y), code.clone() + "L"));
+    }
+    if *x < 3 && hash[1] & 0x0F >= 0x0b {
+        next.push(((x + 1, *y), code.clone() + "R"));
+    }
+    next
 }
@@ -1,7 +1,8 @@
 use clap::Parser;
+use grid::{all_pairs, Grid};
 use itertools::Itertools;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::HashMap,
     fs::File,
     io::{BufRead, BufReader},
 };
@@ -47,11 +48,21 @@ fn main() {
     // We have to start at 0, but otherwise can go any order.
     // The fastest way to do this will be to eliminate the maze and have a graph of the points and weighted edges.
     let mut edges = HashMap::new();
-
-    for i in 0..required_points.len() {
-        for j in (i + 1)..required_points.len() {
+    let distances = all_pairs(
+        &required_points
+            .iter()
+            .map(|(x, y, _)| (*x, *y))
+            .collect_vec(),
+        |point| map.neighbours(*point),
+    );
+
+    for (i, row) in distances.iter().enumerate() {
+        for (j, distance) in row.iter().enumerate().skip(i + 1) {
             // Now lets calculate edges
-            let distance = distance_between(required_points[i], required_points[j], &map);
+            let distance = distance.unwrap_or_else(|| {
+                eprintln!("Can't get from {} to {}", i, j);
+                std::process::exit(1);
+            });
             edges.insert((i, j), distance);
             edges.insert((j, i), distance);
 
@@ -110,42 +121,3 @@ fn main() {
 
     println!("Part 2: {}", shortest_path_length);
 }
-
-fn distance_between(
-    point_a: (usize, usize, u8),
-    point_b: (usize, usize, u8),
-    map: &Vec<Vec<bool>>,
-) -> usize {
-    // Simple BFS - we could / should optimize to pick the most likely candidate position, but the map isn't that big
-    let mut states = VecDeque::new();
-    states.push_back((point_a.0, point_a.1, 0));
-    let mut seen_states = Vec::new();
-
-    while !states.is_empty() {
-        let state = states.pop_front().unwrap();
-        if seen_states.contains(&(state.0, state.1)) {
-            continue;
-        }
-
-        seen_states.push((state.0, state.1));
-
-        if state.0 == point_b.0 && state.1 == point_b.1 {
-            return state.2;
-        }
-
-        if state.1 > 0 && map[state.1 - 1][state.0] {
-            states.push_back((state.0, state.1 - 1, state.2 + 1));
-        }
-        if state.1 < map.len() - 1 && map[state.1 + 1][state.0] {
-            states.push_back((state.0, state.1 + 1, state.2 + 1));
-        }
-        if state.0 > 0 && map[state.1][state.0 - 1] {
-            states.push_back((state.0 - 1, state.1, state.2 + 1));
-        }
-        if state.0 < map[0].len() - 1 && map[state.1][state.0 + 1] {
-            states.push_back((state.0 + 1, state.1, state.2 + 1));
-        }
-    }
-
-    0
-}
@@ -1,177 +1,315 @@
+use assembunny::{
+    cli::{check_width, LimitArgs, ProgramArgs},
+    Instruction, Machine, Native, NativeExit, OutputSink, Profile, Register, Status, Width,
+};
 use clap::Parser;
 use indicatif::ProgressBar;
-use itertools::Itertools;
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-    vec,
-};
+use rayon::prelude::*;
+use std::{collections::HashSet, ops::ControlFlow, sync::Mutex};
 
-#[derive(Clone, Copy, Debug)]
-#[repr(usize)]
-enum Register {
-    A = 0,
-    B,
-    C,
-    D,
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(long)]
+    data_file: String,
+    /// The output to look for, e.g. 3,1,4 for those three values or 0,1,1...
+    /// for 0,1,1 repeated forever
+    #[arg(long, default_value = "0,1...", value_parser = parse_pattern)]
+    expect: Pattern,
+    /// The register to search for a starting value
+    #[arg(long, default_value = "a", value_parser = assembunny::cli::parse_register_name)]
+    search: Register,
+    /// Accept a candidate once this many values of a repeating pattern have
+    /// matched, without waiting for proof that it goes on forever. A finite
+    /// pattern still has to be output in full
+    #[arg(long)]
+    max_outputs: Option<usize>,
+    /// How many threads to search with, all the cores by default. --debug
+    /// always uses one so the trace can be read
+    #[arg(long)]
+    threads: Option<usize>,
+    /// Print how often each instruction ran over the whole search to stderr
+    #[arg(long)]
+    profile: bool,
+    /// How wide the registers are, a candidate that overflows doesn't match
+    #[arg(long, value_enum, default_value_t = Width::I32)]
+    width: Width,
+    /// Set another register before each run, e.g. --reg d=1
+    #[arg(long = "reg", value_name = "REG=VALUE", value_parser = assembunny::cli::parse_assignment)]
+    registers: Vec<(Register, i64)>,
+    // Limits for each candidate, one that hits them doesn't match
+    #[command(flatten)]
+    limits: LimitArgs,
+    #[command(flatten)]
+    program: ProgramArgs,
+    #[arg(long)]
+    debug: bool,
+    /// Compile the program with rustc and run that. Programs using tgl still
+    /// run in the interpreter. It can't be profiled or limited
+    #[arg(long, conflicts_with_all = ["max_steps", "timeout", "profile"])]
+    native: bool,
 }
 
-impl From<&str> for Register {
-    fn from(value: &str) -> Self {
-        match value {
-            "a" => Register::A,
-            "b" => Register::B,
-            "c" => Register::C,
-            "d" => Register::D,
-            _ => panic!("Unexpected register"),
-        }
-    }
+#[derive(Clone, Debug)]
+struct Pattern {
+    values: Vec<i64>,
+    repeating: bool,
 }
 
-#[derive(Clone, Copy, Debug)]
-enum Operand {
-    Value(i32),
-    Register(Register),
+fn parse_pattern(text: &str) -> Result<Pattern, String> {
+    let (text, repeating) = match text.strip_suffix("...") {
+        Some(text) => (text, true),
+        None => (text, false),
+    };
+    let values = text
+        .split(',')
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid output value \"{}\"", value))
+        })
+        .collect::<Result<Vec<i64>, _>>()?;
+    Ok(Pattern { values, repeating })
 }
 
-impl From<&str> for Operand {
-    fn from(value: &str) -> Self {
-        if let Ok(value) = value.parse::<i32>() {
-            Operand::Value(value)
+/// Checks each output against the pattern and stops the machine when one
+/// doesn't match, or when all of a finite pattern has been seen.
+struct Checker<'a> {
+    pattern: &'a Pattern,
+    /// How many values have matched so far
+    position: usize,
+    failed: bool,
+    debug: bool,
+}
+
+impl Checker<'_> {
+    fn is_complete(&self) -> bool {
+        !self.pattern.repeating && self.position == self.pattern.values.len()
+    }
+}
+
+impl OutputSink for Checker<'_> {
+    fn output(&mut self, value: i64) -> ControlFlow<()> {
+        if self.debug {
+            println!("{}", value);
+        }
+        let expected = self.pattern.values[self.position % self.pattern.values.len()];
+        if value != expected {
+            self.failed = true;
+            return ControlFlow::Break(());
+        }
+        self.position += 1;
+        if self.is_complete() {
+            ControlFlow::Break(())
         } else {
-            Operand::Register(value.into())
+            ControlFlow::Continue(())
         }
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-enum Instruction {
-    Nop,
-    Add(Operand, Operand),
-    Copy(Operand, Operand),
-    Increment(Register),
-    Decrement(Register),
-    JumpNotZero(Operand, Operand),
-    Out(Operand),
-}
+/// Like [`matches`] for the compiled program, which can't be profiled.
+fn matches_native(native: &Native, args: &Args, value: i64) -> bool {
+    let mut registers = [0; 4];
+    for (reg, value) in args.registers.iter() {
+        registers[*reg as usize] = *value;
+    }
+    registers[args.search as usize] = value;
 
-impl From<&str> for Instruction {
-    fn from(value: &str) -> Self {
-        let components = value.split_whitespace().collect_vec();
-
-        match components[0] {
-            "nop" => Instruction::Nop,
-            "add" => Instruction::Add(components[1].into(), components[2].into()),
-            "cpy" => Instruction::Copy(components[1].into(), components[2].into()),
-            "inc" => Instruction::Increment(components[1].into()),
-            "dec" => Instruction::Decrement(components[1].into()),
-            "jnz" => Instruction::JumpNotZero(components[1].into(), components[2].into()),
-            "out" => Instruction::Out(components[1].into()),
-            _ => panic!("Unexpected instruction"),
+    let mut checker = Checker {
+        pattern: &args.expect,
+        position: 0,
+        failed: false,
+        debug: args.debug,
+    };
+    let mut snapshots = HashSet::new();
+    let mut proven = false;
+
+    let exit = native.run(&registers, |value, pc, registers| {
+        checker.output(value)?;
+        let state = (pc, *registers, checker.position % args.expect.values.len());
+        if args.expect.repeating
+            && (args
+                .max_outputs
+                .is_some_and(|limit| checker.position >= limit)
+                || !snapshots.insert(state))
+        {
+            proven = true;
+            return ControlFlow::Break(());
+        }
+        ControlFlow::Continue(())
+    });
+    match exit {
+        Ok(NativeExit::Stopped) => proven || checker.is_complete(),
+        Ok(NativeExit::Halted(..) | NativeExit::Overflowed(..)) => false,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
         }
     }
 }
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    #[arg(long)]
-    data_file: String,
-    #[arg(long)]
-    debug: bool,
+/// Whether the program's output matches with the searched register set to
+/// `value`. A finite pattern has to be output in full. A repeating one is
+/// proven once the machine gets back to a state it was in after an earlier
+/// output, at the same point in the pattern, since from there it can only do
+/// the same again, or once `--max-outputs` values of it have matched. Running
+/// into `--max-steps` or `--timeout` first means it doesn't match.
+fn matches(instructions: &[Instruction], args: &Args, value: i64) -> (bool, Profile) {
+    let mut machine = Machine::new(instructions.to_vec());
+    machine.set_width(args.width);
+    for (reg, value) in args.registers.iter() {
+        machine.set_register(*reg, *value);
+    }
+    machine.set_register(args.search, value);
+    args.program.prepare(&mut machine);
+
+    let mut checker = Checker {
+        pattern: &args.expect,
+        position: 0,
+        failed: false,
+        debug: args.debug,
+    };
+    let mut snapshots = HashSet::new();
+    let mut profile = Profile::new(&machine);
+    let limits = args.limits.start();
+
+    let found = loop {
+        let instruction = machine.fetch();
+        if args.debug {
+            if let Some(instruction) = instruction {
+                println!("{}: {}", machine.pc(), instruction);
+            }
+        }
+        let is_out = matches!(instruction, Some(Instruction::Out(_)));
+        match profile.step(&mut machine, &mut checker) {
+            Status::Running => (),
+            Status::Stopped => break checker.is_complete(),
+            Status::Halted | Status::Overflowed => break false,
+        }
+        if let Err(error) = limits.check(&machine, Status::Running) {
+            if args.debug {
+                println!("{}", error);
+            }
+            break false;
+        }
+        if is_out && args.expect.repeating {
+            if args
+                .max_outputs
+                .is_some_and(|limit| checker.position >= limit)
+            {
+                break true;
+            }
+            // The program is part of the state too, tgl can change it
+            let snapshot = (
+                machine.pc(),
+                *machine.registers(),
+                checker.position % args.expect.values.len(),
+                machine.instructions().to_vec(),
+            );
+            if !snapshots.insert(snapshot) {
+                break true;
+            }
+        }
+    };
+    (found, profile)
 }
 
 fn main() {
     let args = Args::parse();
 
-    let file = File::open(&args.data_file).expect("Failed to open file");
-    let reader = BufReader::new(file);
-    let lines: Vec<String> = reader
-        .lines()
-        .map(|line| line.expect("Failed to read line"))
-        .collect();
+    let instructions = assembunny::load_program(&args.data_file).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    });
+    for (reg, value) in args.registers.iter() {
+        if let Err(error) = check_width(args.width, *reg, *value) {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    }
+
+    if args.program.decompile {
+        let mut machine = Machine::new(instructions);
+        args.program.prepare(&mut machine);
+        print!("{}", assembunny::decompile(machine.instructions()));
+        return;
+    }
 
-    let instructions = lines
-        .iter()
-        .map(|line| Instruction::from(line.as_str()))
-        .collect_vec();
+    let directory = assembunny::build_directory();
+    let native = if args.native {
+        Native::build(&instructions, args.width, &directory)
+            .map_err(|error| eprintln!("{}, running in the interpreter", error))
+            .ok()
+    } else {
+        None
+    };
 
-    let mut a = -1;
+    let threads = if args.debug {
+        1
+    } else {
+        args.threads.unwrap_or(0)
+    };
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .expect("Failed to start threads");
 
     let progress = ProgressBar::new_spinner();
+    let total_profile = Mutex::new(Profile::default());
 
-    loop {
-        a += 1;
-        let mut pc: i32 = 0;
-        let mut registers = vec![0; 4];
-        registers[0] = a;
-        let mut last_out = None;
-
-        'exec: while (pc as usize) < instructions.len() {
-            progress.inc(1);
-            progress.set_message(format!("A = {}", a));
-            if args.debug {
-                println!("{}: {:?}", pc, instructions[pc as usize]);
+    // Candidates are handed out a batch at a time. find_first gives the
+    // smallest match in a batch however the threads finish. They stop at the
+    // largest value the register can start with.
+    const BATCH: i64 = 1024;
+    let max = args.width.max();
+    for start in (0..).step_by(BATCH as usize) {
+        if start > max {
+            progress.finish_and_clear();
+            if native.is_some() {
+                let _ = std::fs::remove_dir_all(&directory);
             }
-            match &instructions[pc as usize] {
-                Instruction::Nop => (),
-                Instruction::Add(src, dst) => {
-                    // Adding to an immediate is invalid
-                    if let Operand::Register(reg) = dst {
-                        registers[*reg as usize] += match src {
-                            Operand::Value(val) => *val,
-                            Operand::Register(src) => registers[*src as usize],
-                        };
-                    }
-                }
-                Instruction::Copy(src, dst) => {
-                    // Copying to an immediate is invalid
-                    if let Operand::Register(reg) = dst {
-                        registers[*reg as usize] = match src {
-                            Operand::Value(val) => *val,
-                            Operand::Register(src) => registers[*src as usize],
-                        }
-                    }
-                }
-                Instruction::Increment(reg) => registers[*reg as usize] += 1,
-                Instruction::Decrement(reg) => registers[*reg as usize] -= 1,
-                Instruction::JumpNotZero(operand, dst) => {
-                    if match operand {
-                        Operand::Value(val) => *val,
-                        Operand::Register(reg) => registers[*reg as usize],
-                    } != 0
-                    {
-                        pc += match dst {
-                            Operand::Value(val) => *val,
-                            Operand::Register(reg) => registers[*reg as usize],
-                        };
-
-                        // To offset the += 1 later in this function
-                        pc -= 1;
-                    }
-                }
-                Instruction::Out(src) => match src {
-                    Operand::Value(val) => println!("{}", val),
-                    Operand::Register(reg) => {
-                        let val = registers[*reg as usize];
-                        if last_out.is_none() || last_out.unwrap() != val {
-                            last_out = Some(val);
-                        } else {
-                            break 'exec;
-                        }
-
-                        if args.debug {
-                            println!("{}", val);
+            eprintln!(
+                "No value of {} up to {} matches with {} registers",
+                args.search, max, args.width
+            );
+            std::process::exit(1);
+        }
+        let end = start.saturating_add(BATCH - 1).min(max);
+        let found = pool.install(|| {
+            (start..=end).into_par_iter().find_first(|value| {
+                let found = match &native {
+                    Some(native) => matches_native(native, &args, *value),
+                    None => {
+                        let (found, profile) = matches(&instructions, &args, *value);
+                        if args.profile {
+                            total_profile.lock().unwrap().merge(&profile);
                         }
+                        found
                     }
-                },
+                };
+                progress.inc(1);
+                progress.set_message(format!(
+                    "{} values of {} checked",
+                    progress.position(),
+                    args.search
+                ));
+                found
+            })
+        });
+        if let Some(value) = found {
+            progress.finish_and_clear();
+            if native.is_some() {
+                let _ = std::fs::remove_dir_all(&directory);
             }
-
-            if args.debug {
-                registers.iter().for_each(|reg| print!("{} ", reg));
+            if args.profile {
+                let mut machine = Machine::new(instructions.clone());
+                args.program.prepare(&mut machine);
+                let profile = total_profile.lock().unwrap();
+                eprint!("{}", profile.report(machine.instructions()));
             }
-
-            pc += 1;
+            println!("{} = {}", args.search.to_string().to_uppercase(), value);
+            return;
         }
     }
 }
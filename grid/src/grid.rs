@@ -0,0 +1,31 @@
+/// A cell of a grid, as (x, y) with y going down.
+pub type Point = (usize, usize);
+
+/// A grid of cells that are either open or walls, moved around one step up,
+/// down, left or right at a time.
+pub trait Grid {
+    /// Whether a cell can be moved into. Anything off the grid isn't.
+    fn is_open(&self, point: Point) -> bool;
+
+    /// The open cells next to a point.
+    fn neighbours(&self, point: Point) -> Vec<Point> {
+        let (x, y) = point;
+        [
+            y.checked_sub(1).map(|y| (x, y)),
+            y.checked_add(1).map(|y| (x, y)),
+            x.checked_sub(1).map(|x| (x, y)),
+            x.checked_add(1).map(|x| (x, y)),
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|point| self.is_open(*point))
+        .collect()
+    }
+}
+
+/// Rows of cells, true where they're open.
+impl Grid for Vec<Vec<bool>> {
+    fn is_open(&self, (x, y): Point) -> bool {
+        self.get(y).and_then(|row| row.get(x)).copied() == Some(true)
+    }
+}
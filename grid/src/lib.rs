@@ -0,0 +1,8 @@
+//! Grids and the searches over them shared by the maze days: day13, day17
+//! and day24.
+
+mod grid;
+mod search;
+
+pub use grid::{Grid, Point};
+pub use search::{all_pairs, astar, bfs, dijkstra, Paths, Visit};
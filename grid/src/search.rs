@@ -0,0 +1,179 @@
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::Entry, BinaryHeap, HashMap, VecDeque},
+    hash::Hash,
+    ops::Add,
+};
+
+/// What a breadth-first search does with a node it has reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visit {
+    /// Go on to its neighbours
+    Expand,
+    /// Keep it, but don't go any further from it
+    Skip,
+    /// End the search here
+    Stop,
+}
+
+/// The nodes a search reached, how much it cost to get to each and how.
+#[derive(Clone, Debug)]
+pub struct Paths<N, C> {
+    costs: HashMap<N, C>,
+    previous: HashMap<N, N>,
+}
+
+impl<N: Clone + Eq + Hash, C: Copy> Paths<N, C> {
+    fn new(start: N, zero: C) -> Paths<N, C> {
+        Paths {
+            costs: HashMap::from([(start, zero)]),
+            previous: HashMap::new(),
+        }
+    }
+
+    pub fn cost(&self, node: &N) -> Option<C> {
+        self.costs.get(node).copied()
+    }
+
+    /// Every node reached, with its cost, in no particular order.
+    pub fn reached(&self) -> impl Iterator<Item = (&N, C)> {
+        self.costs.iter().map(|(node, cost)| (node, *cost))
+    }
+
+    /// How many nodes were reached, counting the start.
+    pub fn len(&self) -> usize {
+        self.costs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.costs.is_empty()
+    }
+
+    /// The nodes from the start to this one, both included.
+    pub fn path_to(&self, node: &N) -> Option<Vec<N>> {
+        if !self.costs.contains_key(node) {
+            return None;
+        }
+        let mut path = vec![node.clone()];
+        while let Some(previous) = self.previous.get(path.last().unwrap()) {
+            path.push(previous.clone());
+        }
+        path.reverse();
+        Some(path)
+    }
+}
+
+/// Searches outwards from `start`, a step at a time. `visit` is given each
+/// node as it's reached, nearest first, and its distance, and decides what
+/// happens next.
+pub fn bfs<N, I>(
+    start: N,
+    mut neighbours: impl FnMut(&N) -> I,
+    mut visit: impl FnMut(&N, usize) -> Visit,
+) -> Paths<N, usize>
+where
+    N: Clone + Eq + Hash,
+    I: IntoIterator<Item = N>,
+{
+    let mut paths = Paths::new(start.clone(), 0);
+    let mut queue = VecDeque::from([(start, 0)]);
+
+    while let Some((node, distance)) = queue.pop_front() {
+        match visit(&node, distance) {
+            Visit::Expand => (),
+            Visit::Skip => continue,
+            Visit::Stop => break,
+        }
+        for next in neighbours(&node) {
+            if let Entry::Vacant(entry) = paths.costs.entry(next.clone()) {
+                entry.insert(distance + 1);
+                paths.previous.insert(next.clone(), node.clone());
+                queue.push_back((next, distance + 1));
+            }
+        }
+    }
+    paths
+}
+
+/// The cheapest path from `start` to a node where `is_goal` holds, and its
+/// cost. `neighbours` gives each node's neighbours with the cost of moving
+/// to them.
+pub fn dijkstra<N, C, I>(
+    start: N,
+    neighbours: impl FnMut(&N) -> I,
+    is_goal: impl FnMut(&N) -> bool,
+) -> Option<(C, Vec<N>)>
+where
+    N: Clone + Eq + Hash,
+    C: Copy + Ord + Default + Add<Output = C>,
+    I: IntoIterator<Item = (N, C)>,
+{
+    astar(start, neighbours, |_| C::default(), is_goal)
+}
+
+/// [`dijkstra`], trying first the nodes `heuristic` thinks are closest to a
+/// goal. It must never overestimate for the path to be the cheapest.
+pub fn astar<N, C, I>(
+    start: N,
+    mut neighbours: impl FnMut(&N) -> I,
+    mut heuristic: impl FnMut(&N) -> C,
+    mut is_goal: impl FnMut(&N) -> bool,
+) -> Option<(C, Vec<N>)>
+where
+    N: Clone + Eq + Hash,
+    C: Copy + Ord + Default + Add<Output = C>,
+    I: IntoIterator<Item = (N, C)>,
+{
+    let mut paths = Paths::new(start.clone(), C::default());
+    // Nodes live here so the heap only has to order their estimates, ties
+    // going to the oldest
+    let mut nodes = vec![(start.clone(), C::default())];
+    let mut heap = BinaryHeap::from([(Reverse(heuristic(&start)), Reverse(0))]);
+
+    while let Some((_, Reverse(index))) = heap.pop() {
+        let (node, cost) = nodes[index].clone();
+        if paths.cost(&node) != Some(cost) {
+            // Since found more cheaply
+            continue;
+        }
+        if is_goal(&node) {
+            let path = paths.path_to(&node).unwrap();
+            return Some((cost, path));
+        }
+        for (next, step) in neighbours(&node) {
+            let next_cost = cost + step;
+            if paths.cost(&next).is_some_and(|known| known <= next_cost) {
+                continue;
+            }
+            paths.costs.insert(next.clone(), next_cost);
+            paths.previous.insert(next.clone(), node.clone());
+            heap.push((Reverse(next_cost + heuristic(&next)), Reverse(nodes.len())));
+            nodes.push((next, next_cost));
+        }
+    }
+    None
+}
+
+/// The distance between every pair of `points`, by a search from each that
+/// stops once it has found the rest. `None` where one can't be reached.
+pub fn all_pairs<N, I>(points: &[N], mut neighbours: impl FnMut(&N) -> I) -> Vec<Vec<Option<usize>>>
+where
+    N: Clone + Eq + Hash,
+    I: IntoIterator<Item = N>,
+{
+    points
+        .iter()
+        .map(|from| {
+            let mut remaining = points.len();
+            let paths = bfs(from.clone(), &mut neighbours, |node, _| {
+                remaining -= points.iter().filter(|point| *point == node).count();
+                if remaining == 0 {
+                    Visit::Stop
+                } else {
+                    Visit::Expand
+                }
+            });
+            points.iter().map(|to| paths.cost(to)).collect()
+        })
+        .collect()
+}
@@ -0,0 +1,196 @@
+use grid::{all_pairs, astar, bfs, dijkstra, Grid, Point, Visit};
+
+/// A grid from rows of text, `#` for walls.
+fn maze(rows: &[&str]) -> Vec<Vec<bool>> {
+    rows.iter()
+        .map(|row| row.chars().map(|c| c != '#').collect())
+        .collect()
+}
+
+const MAZE: [&str; 5] = [
+    "#########",
+    "#...#...#",
+    "#.#.#.#.#",
+    "#.#...#.#",
+    "#########",
+];
+
+#[test]
+fn neighbours_stay_on_the_grid() {
+    let grid = maze(&["..", ".#"]);
+    assert_eq!(grid.neighbours((0, 0)), vec![(0, 1), (1, 0)]);
+    assert_eq!(grid.neighbours((1, 0)), vec![(0, 0)]);
+    assert!(!grid.is_open((5, 5)));
+}
+
+#[test]
+fn bfs_finds_shortest_distances() {
+    let grid = maze(&MAZE);
+    let paths = bfs(
+        (1, 1),
+        |point| grid.neighbours(*point),
+        |_, _| Visit::Expand,
+    );
+    assert_eq!(paths.cost(&(1, 1)), Some(0));
+    assert_eq!(paths.cost(&(3, 1)), Some(2));
+    assert_eq!(paths.cost(&(7, 1)), Some(10));
+    assert_eq!(paths.cost(&(0, 0)), None);
+    assert_eq!(paths.len(), 15);
+}
+
+#[test]
+fn path_to_walks_back_to_the_start() {
+    let grid = maze(&MAZE);
+    let paths = bfs(
+        (1, 1),
+        |point| grid.neighbours(*point),
+        |_, _| Visit::Expand,
+    );
+
+    let path = paths.path_to(&(7, 3)).unwrap();
+    assert_eq!(path.first(), Some(&(1, 1)));
+    assert_eq!(path.last(), Some(&(7, 3)));
+    assert_eq!(path.len(), paths.cost(&(7, 3)).unwrap() + 1);
+    for (from, to) in path.iter().zip(path.iter().skip(1)) {
+        assert_eq!(from.0.abs_diff(to.0) + from.1.abs_diff(to.1), 1);
+        assert!(grid.is_open(*to));
+    }
+
+    assert_eq!(paths.path_to(&(1, 1)), Some(vec![(1, 1)]));
+    assert_eq!(paths.path_to(&(0, 0)), None);
+}
+
+#[test]
+fn skip_keeps_a_node_without_going_past_it() {
+    let grid = maze(&MAZE);
+    let paths = bfs(
+        (1, 1),
+        |point| grid.neighbours(*point),
+        |_, distance| {
+            if distance == 2 {
+                Visit::Skip
+            } else {
+                Visit::Expand
+            }
+        },
+    );
+    let mut reached: Vec<Point> = paths.reached().map(|(point, _)| *point).collect();
+    reached.sort();
+    assert_eq!(reached, vec![(1, 1), (1, 2), (1, 3), (2, 1), (3, 1)]);
+    assert!(paths.reached().all(|(_, distance)| distance <= 2));
+}
+
+#[test]
+fn stop_ends_the_search() {
+    let grid = maze(&MAZE);
+    let mut visited = Vec::new();
+    let paths = bfs(
+        (1, 1),
+        |point| grid.neighbours(*point),
+        |point, _| {
+            visited.push(*point);
+            if *point == (3, 2) {
+                Visit::Stop
+            } else {
+                Visit::Expand
+            }
+        },
+    );
+    assert_eq!(visited.last(), Some(&(3, 2)));
+    assert_eq!(paths.cost(&(3, 2)), Some(3));
+    // Nothing further than the stop was visited
+    assert!(visited.iter().all(|point| paths.cost(point).unwrap() <= 3));
+    assert_eq!(paths.cost(&(7, 1)), None);
+}
+
+/// A line of nodes 0..=4 where each step costs 10, with a shortcut from 0
+/// straight to 4 that costs 25.
+fn line(node: &u32) -> Vec<(u32, u32)> {
+    let mut next = Vec::new();
+    if *node < 4 {
+        next.push((node + 1, 10));
+    }
+    if *node == 0 {
+        next.push((4, 25));
+    }
+    next
+}
+
+#[test]
+fn dijkstra_takes_the_cheapest_path_not_the_shortest() {
+    assert_eq!(dijkstra(0, line, |node| *node == 4), Some((25, vec![0, 4])));
+    assert_eq!(
+        dijkstra(1, line, |node| *node == 4),
+        Some((30, vec![1, 2, 3, 4]))
+    );
+    assert_eq!(dijkstra(0, line, |node| *node == 5), None);
+}
+
+#[test]
+fn astar_is_optimal_with_an_admissible_heuristic() {
+    // The heuristic makes A look closest, but it's 100 from the goal. Going
+    // round through B, C and D costs 4.
+    //
+    //   S -1-> A -100-----> G
+    //   |                   ^
+    //   1                   1
+    //   v                   |
+    //   B -1-> C -1-> D ----+
+    let graph = |node: &char| -> Vec<(char, u32)> {
+        match node {
+            'S' => vec![('A', 1), ('B', 1)],
+            'A' => vec![('G', 100)],
+            'B' => vec![('C', 1)],
+            'C' => vec![('D', 1)],
+            'D' => vec![('G', 1)],
+            _ => vec![],
+        }
+    };
+    // Never more than the real cost, and A looks closest
+    let heuristic = |node: &char| match node {
+        'S' => 2,
+        'A' => 1,
+        'B' => 3,
+        'C' => 2,
+        'D' => 1,
+        _ => 0,
+    };
+    let found = astar('S', graph, heuristic, |node| *node == 'G');
+    assert_eq!(found, Some((4, vec!['S', 'B', 'C', 'D', 'G'])));
+    assert_eq!(
+        dijkstra('S', graph, |node| *node == 'G').map(|(cost, _)| cost),
+        Some(4)
+    );
+
+    // On a grid with Manhattan distance it agrees with a breadth-first search
+    let grid = maze(&MAZE);
+    let goal = (7, 3);
+    let found = astar(
+        (1, 1),
+        |point| grid.neighbours(*point).into_iter().map(|next| (next, 1)),
+        |point: &Point| point.0.abs_diff(goal.0) + point.1.abs_diff(goal.1),
+        |point| *point == goal,
+    );
+    let paths = bfs(
+        (1, 1),
+        |point| grid.neighbours(*point),
+        |_, _| Visit::Expand,
+    );
+    assert_eq!(found.map(|(cost, _)| cost), paths.cost(&goal));
+}
+
+#[test]
+fn all_pairs_distances() {
+    let grid = maze(&["#######", "#.....#", "#.###.#", "#######", "#.#...#"]);
+    let points = [(1, 1), (5, 2), (1, 2), (3, 4)];
+    let distances = all_pairs(&points, |point| grid.neighbours(*point));
+    assert_eq!(
+        distances,
+        vec![
+            vec![Some(0), Some(5), Some(1), None],
+            vec![Some(5), Some(0), Some(6), None],
+            vec![Some(1), Some(6), Some(0), None],
+            vec![None, None, None, Some(0)],
+        ]
+    );
+}